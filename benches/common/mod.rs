@@ -0,0 +1,26 @@
+//! Timing helpers shared by the benchmarks.
+use std::time::{Duration, Instant};
+
+/// How many times to time each, keeping the fastest.
+const RUNS: usize = 5;
+
+/// The fastest of `RUNS` calls to `f`, which fills the buffer
+/// it is given.
+pub fn fastest(mut f: impl FnMut(&mut Vec<u8>)) -> (Vec<u8>, Duration) {
+    let mut best = Duration::MAX;
+    let mut out: Vec<u8> = Vec::new();
+    for _ in 0..RUNS {
+        out.clear();
+        let start = Instant::now();
+        f(&mut out);
+        best = best.min(start.elapsed());
+    }
+    (out, best)
+}
+
+pub fn megabytes_per_second(
+    len: usize,
+    elapsed: Duration,
+) -> f64 {
+    len as f64 / elapsed.as_secs_f64() / 1_000_000.0
+}
@@ -0,0 +1,33 @@
+//! Compares packing a byte at a time through `Packer::pack`
+//! with `pack_bulk`. Run with
+//! `cargo bench --features alloc --bench pack`.
+mod common;
+
+use common::{fastest, megabytes_per_second};
+use meatpack::{Packer, pack_bulk};
+
+/// How many copies of the test file to pack, about 10MB.
+const REPEATS: usize = 200;
+
+fn main() {
+    let gcode = include_bytes!("../test_files/box.gcode").repeat(REPEATS);
+
+    for (strip_comments, strip_whitespace) in [(false, false), (true, false), (true, true)] {
+        let (per_byte, per_byte_elapsed) = fastest(|per_byte| {
+            Packer::<256>::pack_slice(&gcode, per_byte, strip_comments, strip_whitespace).unwrap();
+        });
+        let (bulk, bulk_elapsed) = fastest(|bulk| {
+            pack_bulk(&gcode, bulk, strip_comments, strip_whitespace).unwrap();
+        });
+
+        assert_eq!(per_byte, bulk);
+        println!(
+            "strip comments {}, strip whitespace {}: per byte {:.1} MB/s, bulk {:.1} MB/s ({:.1}x)",
+            strip_comments,
+            strip_whitespace,
+            megabytes_per_second(gcode.len(), per_byte_elapsed),
+            megabytes_per_second(gcode.len(), bulk_elapsed),
+            per_byte_elapsed.as_secs_f64() / bulk_elapsed.as_secs_f64(),
+        );
+    }
+}
@@ -0,0 +1,36 @@
+//! Compares unpacking a byte at a time through `Unpacker::unpack`
+//! with `unpack_bulk`. Run with
+//! `cargo bench --features alloc --bench unpack`.
+mod common;
+
+use common::{fastest, megabytes_per_second};
+use meatpack::{Unpacker, pack_bulk, unpack_bulk};
+
+/// How many copies of the test file to pack and unpack, about 10MB.
+const REPEATS: usize = 200;
+
+fn main() {
+    let gcode = include_bytes!("../test_files/box.gcode").repeat(REPEATS);
+
+    for (strip_comments, strip_whitespace) in [(false, false), (true, false), (true, true)] {
+        let mut meat: Vec<u8> = Vec::new();
+        pack_bulk(&gcode, &mut meat, strip_comments, strip_whitespace).unwrap();
+
+        let (per_byte, per_byte_elapsed) = fastest(|per_byte| {
+            Unpacker::<256>::unpack_slice(&meat, per_byte).unwrap();
+        });
+        let (bulk, bulk_elapsed) = fastest(|bulk| {
+            unpack_bulk(&meat, bulk).unwrap();
+        });
+
+        assert_eq!(per_byte, bulk);
+        println!(
+            "strip comments {}, strip whitespace {}: per byte {:.1} MB/s, bulk {:.1} MB/s ({:.1}x)",
+            strip_comments,
+            strip_whitespace,
+            megabytes_per_second(meat.len(), per_byte_elapsed),
+            megabytes_per_second(meat.len(), bulk_elapsed),
+            per_byte_elapsed.as_secs_f64() / bulk_elapsed.as_secs_f64(),
+        );
+    }
+}
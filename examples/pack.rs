@@ -23,6 +23,9 @@ M204 P4000 R1200 T4000
                 println!("{:?}", line);
             }
             Ok(MeatPackResult::WaitingForNextByte) => {}
+            Ok(MeatPackResult::Command(_)) => {}
+            Ok(MeatPackResult::Resynced(_)) => {}
+            Ok(MeatPackResult::Partial(_)) => {}
             Err(e) => {
                 println!("{:?}", e);
                 panic!()
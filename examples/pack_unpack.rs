@@ -34,6 +34,9 @@ M204 P4000 R1200 T4000
                 out.extend(line);
             }
             Ok(MeatPackResult::WaitingForNextByte) => {}
+            Ok(MeatPackResult::Command(_)) => {}
+            Ok(MeatPackResult::Resynced(_)) => {}
+            Ok(MeatPackResult::Partial(_)) => {}
             Err(e) => println!("{:?}", e),
         }
     }
@@ -51,6 +54,9 @@ M204 P4000 R1200 T4000
         let res = unpacker.unpack(byte);
         match res {
             Ok(MeatPackResult::WaitingForNextByte) => {}
+            Ok(MeatPackResult::Command(_)) => {}
+            Ok(MeatPackResult::Resynced(_)) => {}
+            Ok(MeatPackResult::Partial(_)) => {}
             Ok(MeatPackResult::Line(line)) => {
                 // If in std.
                 for byte in line {
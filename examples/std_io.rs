@@ -0,0 +1,33 @@
+use std::{
+    env,
+    fs::File,
+    io::{self, BufReader},
+};
+
+use meatpack::{MeatPackReader, MeatPackWriter, Packer, Unpacker};
+
+fn main() {
+    // Create the path to the gcode file
+    let mut path = env::current_dir().unwrap();
+    path.push("test_files");
+    path.push("box.gcode");
+
+    // Pack the file by copying it into a MeatPackWriter.
+    // The header is written for us.
+    let mut gcode = BufReader::new(File::open(path).unwrap());
+    let mut writer = MeatPackWriter::new(Vec::new(), Packer::<128>::new(false, true));
+    let gl = io::copy(&mut gcode, &mut writer).unwrap();
+    let meat = writer.finish().unwrap();
+
+    // And copy it back out through a MeatPackReader.
+    let mut reader = MeatPackReader::new(meat.as_slice(), Unpacker::<128>::default());
+    let mut unpacked = Vec::new();
+    io::copy(&mut reader, &mut unpacked).unwrap();
+
+    println!(
+        "Gcode: {}, Meat: {}, Unpacked: {}",
+        gl,
+        meat.len(),
+        unpacked.len()
+    );
+}
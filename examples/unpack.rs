@@ -24,6 +24,11 @@ fn main() {
             Ok(MeatPackResult::WaitingForNextByte) => {
                 //println!("Waiting for next byte");
             }
+            Ok(MeatPackResult::Command(cmd)) => {
+                println!("{:?}", cmd);
+            }
+            Ok(MeatPackResult::Partial(_)) => {}
+            Ok(MeatPackResult::Resynced(_)) => {}
             Ok(MeatPackResult::Line(line)) => {
                 let line = str::from_utf8(line).unwrap();
                 println!("{:?}", line);
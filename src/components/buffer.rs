@@ -0,0 +1,104 @@
+#[cfg(feature = "alloc")]
+use alloc::{boxed::Box, vec::Vec};
+
+/// The storage a `BufferedPacker` or `BufferedUnpacker` builds
+/// its lines in. Storage that can grow makes room as the line
+/// gets longer, otherwise a line that does not fit is handled
+/// by the `OverflowPolicy`.
+///
+/// It is implemented for fixed arrays, borrowed `&mut [u8]`
+/// slices, `heapless::Vec` (with the `heapless` feature) and
+/// `Vec` and `Box<[u8]>` (with the `alloc` feature). Implement
+/// it for anything else that can hand out a slice of bytes.
+pub trait LineBuffer {
+    /// The bytes currently available to hold a line.
+    fn as_bytes(&self) -> &[u8];
+
+    /// The bytes currently available to hold a line.
+    fn as_bytes_mut(&mut self) -> &mut [u8];
+
+    /// Makes room for a line of `len` bytes if the storage
+    /// can grow, returning whether a line of `len` bytes fits.
+    fn make_room(
+        &mut self,
+        len: usize,
+    ) -> bool {
+        len <= self.as_bytes().len()
+    }
+}
+
+impl<const S: usize> LineBuffer for [u8; S] {
+    fn as_bytes(&self) -> &[u8] {
+        self
+    }
+
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+/// A buffer borrowed from elsewhere, such as a static placed
+/// in a particular linker section.
+impl LineBuffer for &mut [u8] {
+    fn as_bytes(&self) -> &[u8] {
+        self
+    }
+
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+/// A buffer that grows up to its fixed capacity `N`.
+#[cfg(feature = "heapless")]
+impl<const N: usize> LineBuffer for heapless::Vec<u8, N> {
+    fn as_bytes(&self) -> &[u8] {
+        self
+    }
+
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        self
+    }
+
+    fn make_room(
+        &mut self,
+        len: usize,
+    ) -> bool {
+        len <= self.len() || self.resize(len, 0).is_ok()
+    }
+}
+
+/// A buffer whose size is chosen at runtime but is then fixed.
+#[cfg(feature = "alloc")]
+impl LineBuffer for Box<[u8]> {
+    fn as_bytes(&self) -> &[u8] {
+        self
+    }
+
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+/// A buffer that starts at its current length and doubles
+/// in size whenever a line needs more room.
+#[cfg(feature = "alloc")]
+impl LineBuffer for Vec<u8> {
+    fn as_bytes(&self) -> &[u8] {
+        self
+    }
+
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        self
+    }
+
+    fn make_room(
+        &mut self,
+        len: usize,
+    ) -> bool {
+        if len > self.len() {
+            self.resize(len.max(self.len() * 2), 0);
+        }
+        true
+    }
+}
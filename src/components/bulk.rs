@@ -0,0 +1,125 @@
+use alloc::vec::Vec;
+
+use crate::components::meat::{
+    COMMENT_START_BYTE, FULLWIDTH_BYTE, LINEFEED_BYTE, MEATPACK_HEADER, MeatPackCommand,
+    MeatPackError, NO_SPACES_COMMAND, PACK_TABLE, SIGNAL_BYTE,
+};
+use crate::components::stream_unpack::{StreamUnpacker, Unpacked};
+
+/// The packed byte of a line with an even number of characters,
+/// \n\n with the second dropped when unpacked.
+const LINE_END_BYTE: u8 = 0b1100_1100;
+
+/// Packs a whole slice of gcode into `out_buf`, giving the same
+/// bytes as `Packer::pack_slice` with the default options.
+///
+/// Rather than stepping a `Packer` through every byte it works a
+/// line at a time, looking characters up in a precomputed table,
+/// and has no limit on the length of a line. Reserved bytes are
+/// rejected as with `ReservedBytePolicy::Error`.
+pub fn pack_bulk(
+    in_buf: &[u8],
+    out_buf: &mut Vec<u8>,
+    strip_comments: bool,
+    strip_whitespace: bool,
+) -> Result<(), MeatPackError> {
+    if in_buf.is_empty() {
+        return Err(MeatPackError::EmptyBuffer);
+    }
+    let table = &PACK_TABLE[usize::from(strip_whitespace)];
+
+    out_buf.reserve(in_buf.len() / 2 + MEATPACK_HEADER.len() + NO_SPACES_COMMAND.len());
+    out_buf.extend(MEATPACK_HEADER.as_slice());
+    if strip_whitespace {
+        out_buf.extend(NO_SPACES_COMMAND.as_slice());
+    }
+
+    for line in in_buf.split_inclusive(|b| *b == LINEFEED_BYTE) {
+        let (body, terminated) = match line.split_last() {
+            Some((last, body)) if *last == LINEFEED_BYTE => (body, true),
+            _ => (line, false),
+        };
+        let body = match body.iter().position(|b| *b == COMMENT_START_BYTE) {
+            Some(end) if strip_comments => &body[..end],
+            _ => body,
+        };
+
+        let mut held = None;
+        let mut empty = true;
+        for &c in body {
+            if strip_whitespace && (c == b' ' || c == b'\t') {
+                continue;
+            }
+            if c == SIGNAL_BYTE || c == 0 {
+                return Err(MeatPackError::ReservedByte(c));
+            }
+            empty = false;
+            match held.take() {
+                Some(least) => push_pair(out_buf, table, least, c),
+                None => held = Some(c),
+            }
+        }
+
+        match (held, terminated) {
+            (Some(least), true) => push_pair(out_buf, table, least, LINEFEED_BYTE),
+            // Empty lines are omitted.
+            (None, true) if !empty => out_buf.push(LINE_END_BYTE),
+            // A lone character at the end can't be packed.
+            (Some(c), false) => {
+                out_buf.extend(MeatPackCommand::PackingDisabled.to_bytes());
+                out_buf.push(c);
+                out_buf.extend(MeatPackCommand::PackingEnabled.to_bytes());
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Packs two characters into a byte followed by those that have
+/// to be sent fullwidth.
+fn push_pair(
+    out_buf: &mut Vec<u8>,
+    table: &[u8; 256],
+    least: u8,
+    most: u8,
+) {
+    let (l, m) = (table[usize::from(least)], table[usize::from(most)]);
+    out_buf.push(m << 4 | l);
+    if l == FULLWIDTH_BYTE {
+        out_buf.push(least);
+    }
+    if m == FULLWIDTH_BYTE {
+        out_buf.push(most);
+    }
+}
+
+/// Unpacks a whole slice of meatpacked data into `out_buf`, giving
+/// the same bytes as `Unpacker::unpack_slice` with the default
+/// options.
+///
+/// Runs of packed bytes without a fullwidth marker, and of plain
+/// text without a signal byte, are decoded straight from a
+/// precomputed table. Only fullwidth characters and command
+/// sequences go through the `StreamUnpacker`. There is no limit on
+/// the length of a line.
+pub fn unpack_bulk(
+    in_buf: &[u8],
+    out_buf: &mut Vec<u8>,
+) -> Result<(), MeatPackError> {
+    let mut stream = StreamUnpacker::default();
+    out_buf.reserve(in_buf.len() * 2);
+
+    let mut pos = 0;
+    while pos < in_buf.len() {
+        pos += stream.unpack_run(&in_buf[pos..], out_buf);
+        if let Some(byte) = in_buf.get(pos) {
+            if let Unpacked::Chars(chars) = stream.unpack(byte)? {
+                out_buf.extend(chars.iter());
+            }
+            pos += 1;
+        }
+    }
+    stream.finish()
+}
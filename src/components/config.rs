@@ -0,0 +1,103 @@
+use crate::components::meat::{Emitted, MeatPackError};
+
+/// The MeatPack protocol version implemented by the crate.
+pub static PROTOCOL_VERSION: u8 = 1;
+
+/// The longest report line, `[MP] PV01 OFF ESP\n`.
+const REPORT_LEN: usize = 18;
+
+/// The MeatPack state that firmware reports after receiving a
+/// command, most notably in reply to a `QueryConfig`.
+///
+/// The report takes the form `[MP] PV01 ON NSP` where `ON`/`OFF`
+/// states whether packing is active and `NSP`/`ESP` whether no
+/// spaces is enabled or spaces are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeatPackConfig {
+    pub protocol_version: u8,
+    pub packing: bool,
+    pub no_spaces: bool,
+}
+
+impl MeatPackConfig {
+    /// Create a config for the protocol version implemented by the crate.
+    pub fn new(
+        packing: bool,
+        no_spaces: bool,
+    ) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            packing,
+            no_spaces,
+        }
+    }
+
+    /// Produces the firmware style report line, terminated with a `\n`.
+    /// Protocol versions are reported as two digits.
+    pub fn report(&self) -> Emitted<REPORT_LEN> {
+        let mut out = Emitted::new();
+        for b in b"[MP] PV" {
+            out.push(*b);
+        }
+        let version = self.protocol_version % 100;
+        out.push(b'0' + version / 10);
+        out.push(b'0' + version % 10);
+        let packing: &[u8] = if self.packing { b" ON" } else { b" OFF" };
+        let no_spaces: &[u8] = if self.no_spaces { b" NSP\n" } else { b" ESP\n" };
+        for b in packing.iter().chain(no_spaces) {
+            out.push(*b);
+        }
+        out
+    }
+
+    /// Parses a report line received from firmware. Surrounding
+    /// whitespace (including the leading space some firmware adds
+    /// and either line ending) is ignored.
+    pub fn parse(line: &[u8]) -> Result<Self, MeatPackError> {
+        let mut tokens = line
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|token| !token.is_empty());
+
+        if tokens.next() != Some(b"[MP]".as_slice()) {
+            return Err(MeatPackError::InvalidConfigReport);
+        }
+        let protocol_version = match tokens.next() {
+            Some([b'P', b'V', digits @ ..]) if !digits.is_empty() => parse_version(digits)?,
+            _ => return Err(MeatPackError::InvalidConfigReport),
+        };
+        let packing = match tokens.next() {
+            Some(b"ON") => true,
+            Some(b"OFF") => false,
+            _ => return Err(MeatPackError::InvalidConfigReport),
+        };
+        let no_spaces = match tokens.next() {
+            Some(b"NSP") => true,
+            Some(b"ESP") => false,
+            _ => return Err(MeatPackError::InvalidConfigReport),
+        };
+        if tokens.next().is_some() {
+            return Err(MeatPackError::InvalidConfigReport);
+        }
+
+        Ok(Self {
+            protocol_version,
+            packing,
+            no_spaces,
+        })
+    }
+}
+
+/// Parses the decimal digits of a protocol version.
+fn parse_version(digits: &[u8]) -> Result<u8, MeatPackError> {
+    let mut version: u8 = 0;
+    for d in digits {
+        if !d.is_ascii_digit() {
+            return Err(MeatPackError::InvalidConfigReport);
+        }
+        version = version
+            .checked_mul(10)
+            .and_then(|v| v.checked_add(d - b'0'))
+            .ok_or(MeatPackError::InvalidConfigReport)?;
+    }
+    Ok(version)
+}
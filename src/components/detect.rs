@@ -0,0 +1,120 @@
+use core::str::from_utf8;
+
+use crate::components::meat::{MEATPACK_HEADER, MeatPackCommand, NO_SPACES_COMMAND};
+use crate::components::stream_unpack::{StreamUnpacker, Unpacked};
+
+#[cfg(feature = "std")]
+use std::io::{self, BufRead};
+
+/// Plain gcode is taken to be packed once more than one byte
+/// in this many is not text.
+const BINARY_RATIO: usize = 8;
+
+/// What the start of some data looks like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Detection {
+    /// Starts with the `MEATPACK_HEADER`, followed by the
+    /// `NO_SPACES_COMMAND` if `no_spaces` is set.
+    Header { no_spaces: bool },
+    /// Has no header but too few of the bytes are text for
+    /// it to be plain gcode. `no_spaces` is inferred from what
+    /// follows the code shared by space and `E`.
+    Headerless { no_spaces: bool },
+    /// Plain gcode.
+    Plain,
+}
+
+impl Detection {
+    /// Whether the data is meatpacked.
+    pub fn is_packed(&self) -> bool {
+        !matches!(self, Detection::Plain)
+    }
+}
+
+/// Inspects a prefix of some data, such as the first few
+/// hundred bytes of a file, and reports whether it is meatpacked
+/// or plain gcode. Without a header the data is judged on how
+/// many of the bytes are text: printable ASCII, whitespace or
+/// part of a UTF-8 character, as found in comments and `M117`
+/// messages. Headerless data is taken to be packed without
+/// spaces when that code is mostly followed by a number, as `E`
+/// is in `E0.5` but a space rarely is.
+pub fn detect(prefix: &[u8]) -> Detection {
+    if let Some(rest) = prefix.strip_prefix(&MEATPACK_HEADER) {
+        return Detection::Header {
+            no_spaces: rest.starts_with(&NO_SPACES_COMMAND),
+        };
+    }
+    if binary_bytes(prefix) * BINARY_RATIO > prefix.len() {
+        Detection::Headerless {
+            no_spaces: no_spaces(prefix),
+        }
+    } else {
+        Detection::Plain
+    }
+}
+
+/// Counts the bytes that are not text. A UTF-8 character cut
+/// off at the end of the prefix is taken to be text.
+fn binary_bytes(prefix: &[u8]) -> usize {
+    let control = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .filter(|b| b.is_ascii_control() && ![b'\t', b'\r', b'\n'].contains(b))
+            .count()
+    };
+    let mut binary = 0;
+    let mut rest = prefix;
+    loop {
+        match from_utf8(rest) {
+            Ok(text) => return binary + control(text.as_bytes()),
+            Err(e) => {
+                let (valid, invalid) = rest.split_at(e.valid_up_to());
+                binary += control(valid);
+                match e.error_len() {
+                    Some(len) => {
+                        binary += len;
+                        rest = &invalid[len..];
+                    }
+                    None => return binary,
+                }
+            }
+        }
+    }
+}
+
+/// Unpacks headerless data with spaces and counts whether the
+/// spaces, which are `E` without them, mostly come before a number.
+fn no_spaces(prefix: &[u8]) -> bool {
+    let mut unpacker =
+        StreamUnpacker::default().with_detection(Detection::Headerless { no_spaces: false });
+    let (mut numbers, mut others) = (0usize, 0usize);
+    let mut after_space = false;
+    for byte in prefix {
+        match unpacker.unpack(byte) {
+            Ok(Unpacked::Command(MeatPackCommand::NoSpacesEnabled)) => return true,
+            Ok(Unpacked::Command(MeatPackCommand::NoSpacesDisabled)) => return false,
+            Ok(unpacked) => {
+                for c in unpacked.chars() {
+                    if after_space {
+                        if c.is_ascii_digit() || [b'.', b'-'].contains(c) {
+                            numbers += 1;
+                        } else {
+                            others += 1;
+                        }
+                    }
+                    after_space = *c == b' ';
+                }
+            }
+            Err(_) => after_space = false,
+        }
+    }
+    numbers > others
+}
+
+/// Detects whether a reader holds meatpacked or plain gcode
+/// from the bytes it has buffered, without consuming them.
+#[cfg(feature = "std")]
+pub fn detect_reader<R: BufRead>(reader: &mut R) -> io::Result<Detection> {
+    Ok(detect(reader.fill_buf()?))
+}
@@ -0,0 +1,240 @@
+use std::io::{self, Read, Write};
+
+use crate::components::buffer::LineBuffer;
+use crate::components::meat::{MeatPackError, MeatPackResult, PositionedError};
+use crate::{BufferedPacker, BufferedUnpacker};
+
+/// The number of bytes the reader pulls from its source at a time.
+const CHUNK_SIZE: usize = 512;
+
+impl From<MeatPackError> for io::Error {
+    fn from(e: MeatPackError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+impl From<PositionedError> for io::Error {
+    fn from(e: PositionedError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// Wraps a `Write` and meatpacks everything written to it.
+/// The `MEATPACK_HEADER` (and `NO_SPACES_COMMAND` if the packer
+/// strips whitespace) is written ahead of the first line.
+///
+/// Lines are only forwarded once they are complete so call
+/// `finish` once you are done to write out an unterminated
+/// final line. A writer that is dropped instead makes a best
+/// effort to write it, ignoring any error, as `BufWriter` does.
+pub struct MeatPackWriter<W: Write, B: LineBuffer> {
+    /// Only `None` once `finish` has handed it back.
+    inner: Option<W>,
+    packer: BufferedPacker<B>,
+    header_written: bool,
+    /// An error hit part way through a write, returned by the
+    /// next call once the bytes before it have been reported.
+    error: Option<PositionedError>,
+}
+
+impl<W: Write, B: LineBuffer> MeatPackWriter<W, B> {
+    /// Create a new writer that packs into `inner` using the
+    /// provided packer.
+    pub fn new(
+        inner: W,
+        packer: BufferedPacker<B>,
+    ) -> Self {
+        Self {
+            inner: Some(inner),
+            packer,
+            header_written: false,
+            error: None,
+        }
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Writes the header if it has not been written yet.
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            let (header, len) = self.packer.header();
+            self.inner.as_mut().unwrap().write_all(&header[..len])?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    /// Writes any outstanding header and an unterminated final
+    /// line and flushes the underlying writer.
+    fn finish_line(&mut self) -> io::Result<()> {
+        self.write_header()?;
+        let inner = self.inner.as_mut().unwrap();
+        if let Some(line) = self.packer.finish(false)? {
+            inner.write_all(line)?;
+        }
+        inner.flush()
+    }
+
+    /// Writes any outstanding header and an unterminated final
+    /// line, flushes the underlying writer and returns it.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.finish_line()?;
+        Ok(self.inner.take().unwrap())
+    }
+}
+
+impl<W: Write, B: LineBuffer> Drop for MeatPackWriter<W, B> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.finish_line();
+        }
+    }
+}
+
+impl<W: Write, B: LineBuffer> Write for MeatPackWriter<W, B> {
+    fn write(
+        &mut self,
+        buf: &[u8],
+    ) -> io::Result<usize> {
+        if let Some(e) = self.error.take() {
+            return Err(e.into());
+        }
+        self.write_header()?;
+        let inner = self.inner.as_mut().unwrap();
+        for (n, b) in buf.iter().enumerate() {
+            match self.packer.pack(b) {
+                Ok(MeatPackResult::Line(line)) | Ok(MeatPackResult::Partial(line)) => {
+                    inner.write_all(line)?
+                }
+                Ok(
+                    MeatPackResult::WaitingForNextByte
+                    | MeatPackResult::Command(_)
+                    | MeatPackResult::Resynced(_),
+                ) => {}
+                // Report the bytes packed so far and leave the
+                // error for the next call.
+                Err(e) if n > 0 => {
+                    self.error = Some(e);
+                    return Ok(n);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(buf.len())
+    }
+
+    /// Flushes the underlying writer. Partially packed lines
+    /// remain in the packer until they are terminated.
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_header()?;
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+/// Wraps a `Read` of meatpacked data and yields the unpacked
+/// gcode. Header and command sequences are handled by the
+/// unpacker so the source can be read from the very start.
+/// An unterminated final line is yielded once the source ends.
+pub struct MeatPackReader<R: Read, B: LineBuffer> {
+    inner: R,
+    unpacker: BufferedUnpacker<B>,
+    chunk: [u8; CHUNK_SIZE],
+    chunk_pos: usize,
+    chunk_len: usize,
+    line_pos: usize,
+    line_len: usize,
+    eof: bool,
+    finished: bool,
+}
+
+impl<R: Read, B: LineBuffer> MeatPackReader<R, B> {
+    /// Create a new reader that unpacks `inner` using the
+    /// provided unpacker.
+    pub fn new(
+        inner: R,
+        unpacker: BufferedUnpacker<B>,
+    ) -> Self {
+        Self {
+            inner,
+            unpacker,
+            chunk: [0u8; CHUNK_SIZE],
+            chunk_pos: 0,
+            chunk_len: 0,
+            line_pos: 0,
+            line_len: 0,
+            eof: false,
+            finished: false,
+        }
+    }
+
+    /// Returns a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Consumes the reader returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read, B: LineBuffer> Read for MeatPackReader<R, B> {
+    fn read(
+        &mut self,
+        buf: &mut [u8],
+    ) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            // Hand out what is left of the last unpacked line.
+            if self.line_pos < self.line_len {
+                let line = &self.unpacker.line()[self.line_pos..self.line_len];
+                let n = line.len().min(buf.len());
+                buf[..n].copy_from_slice(&line[..n]);
+                self.line_pos += n;
+                return Ok(n);
+            }
+            // Top up the chunk from the source.
+            if self.chunk_pos == self.chunk_len {
+                if self.finished {
+                    return Ok(0);
+                }
+                if self.eof {
+                    // Hand out an unterminated final line.
+                    self.finished = true;
+                    match self.unpacker.finish(false) {
+                        Ok(line) => {
+                            self.line_pos = 0;
+                            self.line_len = line.map_or(0, <[u8]>::len);
+                        }
+                        Err(e) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, e)),
+                    }
+                    continue;
+                }
+                self.chunk_len = match self.inner.read(&mut self.chunk) {
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                self.chunk_pos = 0;
+                self.eof = self.chunk_len == 0;
+                continue;
+            }
+            let b = self.chunk[self.chunk_pos];
+            self.chunk_pos += 1;
+            match self.unpacker.unpack(&b)? {
+                MeatPackResult::Line(line) | MeatPackResult::Partial(line) => {
+                    self.line_pos = 0;
+                    self.line_len = line.len();
+                }
+                MeatPackResult::WaitingForNextByte
+                | MeatPackResult::Command(_)
+                | MeatPackResult::Resynced(_) => {}
+            }
+        }
+    }
+}
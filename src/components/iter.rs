@@ -0,0 +1,213 @@
+use crate::components::buffer::LineBuffer;
+use crate::components::meat::{MeatPackError, PositionedError, Progress};
+use crate::{BufferedPacker, BufferedUnpacker, Packer, Unpacker};
+
+/// The line capacity used by the `meatpack` and `meatunpack`
+/// extension methods.
+const DEFAULT_CAPACITY: usize = 128;
+
+/// An iterator adapter that lazily meatpacks the bytes of the
+/// iterator it wraps, starting with the header. An unterminated
+/// final line is finished once the input ends.
+///
+/// Yields an error and then stops if the packer errors.
+pub struct PackIter<I, B: LineBuffer> {
+    iter: I,
+    packer: BufferedPacker<B>,
+    finished: bool,
+    done: bool,
+}
+
+impl<I: Iterator<Item = u8>, B: LineBuffer> PackIter<I, B> {
+    /// Wrap an iterator of gcode bytes with the provided packer.
+    pub fn new(
+        iter: I,
+        packer: BufferedPacker<B>,
+    ) -> Self {
+        Self {
+            iter,
+            packer,
+            finished: false,
+            done: false,
+        }
+    }
+}
+
+impl<I: Iterator<Item = u8>, B: LineBuffer> Iterator for PackIter<I, B> {
+    type Item = Result<u8, MeatPackError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        next_byte(
+            &mut self.packer,
+            &mut self.iter,
+            &mut self.finished,
+            &mut self.done,
+        )
+    }
+}
+
+/// An iterator adapter that lazily unpacks the meatpacked
+/// bytes of the iterator it wraps. An unterminated final line
+/// is finished once the input ends.
+///
+/// Yields an error and then stops if the unpacker errors or the
+/// input ends part way through a character.
+pub struct UnpackIter<I, B: LineBuffer> {
+    iter: I,
+    unpacker: BufferedUnpacker<B>,
+    finished: bool,
+    done: bool,
+}
+
+impl<I: Iterator<Item = u8>, B: LineBuffer> UnpackIter<I, B> {
+    /// Wrap an iterator of meatpacked bytes with the provided unpacker.
+    pub fn new(
+        iter: I,
+        unpacker: BufferedUnpacker<B>,
+    ) -> Self {
+        Self {
+            iter,
+            unpacker,
+            finished: false,
+            done: false,
+        }
+    }
+}
+
+impl<I: Iterator<Item = u8>, B: LineBuffer> Iterator for UnpackIter<I, B> {
+    type Item = Result<u8, MeatPackError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        next_byte(
+            &mut self.unpacker,
+            &mut self.iter,
+            &mut self.finished,
+            &mut self.done,
+        )
+    }
+}
+
+/// The slice based interface shared by the packer and unpacker
+/// that the iterator adapters are built on.
+trait SliceCodec {
+    fn process(
+        &mut self,
+        in_buf: &[u8],
+        out_buf: &mut [u8],
+    ) -> Result<Progress, PositionedError>;
+
+    fn finish(
+        &mut self,
+        out_buf: &mut [u8],
+    ) -> Result<usize, PositionedError>;
+}
+
+impl<B: LineBuffer> SliceCodec for BufferedPacker<B> {
+    fn process(
+        &mut self,
+        in_buf: &[u8],
+        out_buf: &mut [u8],
+    ) -> Result<Progress, PositionedError> {
+        self.pack_into(in_buf, out_buf)
+    }
+
+    fn finish(
+        &mut self,
+        out_buf: &mut [u8],
+    ) -> Result<usize, PositionedError> {
+        self.finish_into(out_buf)
+    }
+}
+
+impl<B: LineBuffer> SliceCodec for BufferedUnpacker<B> {
+    fn process(
+        &mut self,
+        in_buf: &[u8],
+        out_buf: &mut [u8],
+    ) -> Result<Progress, PositionedError> {
+        self.unpack_into(in_buf, out_buf)
+    }
+
+    fn finish(
+        &mut self,
+        out_buf: &mut [u8],
+    ) -> Result<usize, PositionedError> {
+        self.finish_into(out_buf)
+    }
+}
+
+/// Pulls bytes through the codec until it has one to hand out.
+fn next_byte<C: SliceCodec, I: Iterator<Item = u8>>(
+    codec: &mut C,
+    iter: &mut I,
+    finished: &mut bool,
+    done: &mut bool,
+) -> Option<Result<u8, MeatPackError>> {
+    let mut out = [0u8; 1];
+    while !*done {
+        // Drain anything already waiting (the header or a completed
+        // line) before feeding the codec. Once drained the next byte
+        // is always consumed, and once the input ends the final line
+        // is finished and drained in the same way.
+        let result = match codec.process(&[], &mut out) {
+            Ok(progress) if progress.written == 1 => return Some(Ok(out[0])),
+            Ok(_) if *finished => {
+                *done = true;
+                return None;
+            }
+            Ok(_) => match iter.next() {
+                Some(b) => codec.process(&[b], &mut out),
+                None => {
+                    *finished = true;
+                    codec.finish(&mut out).map(|written| Progress {
+                        consumed: 0,
+                        written,
+                    })
+                }
+            },
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(progress) if progress.written == 1 => return Some(Ok(out[0])),
+            Ok(_) => {}
+            Err(e) => {
+                *done = true;
+                return Some(Err(e.error));
+            }
+        }
+    }
+    None
+}
+
+/// Adds `meatpack` and `meatunpack` to any iterator of bytes.
+pub trait MeatPackIterExt: Iterator<Item = u8> + Sized {
+    /// Meatpacks the bytes using a default `Packer`.
+    fn meatpack(self) -> PackIter<Self, [u8; DEFAULT_CAPACITY]> {
+        PackIter::new(self, Packer::default())
+    }
+
+    /// Meatpacks the bytes using the provided packer, with
+    /// whichever line buffer it was built on.
+    fn meatpack_with<B: LineBuffer>(
+        self,
+        packer: BufferedPacker<B>,
+    ) -> PackIter<Self, B> {
+        PackIter::new(self, packer)
+    }
+
+    /// Unpacks the bytes using a default `Unpacker`.
+    fn meatunpack(self) -> UnpackIter<Self, [u8; DEFAULT_CAPACITY]> {
+        UnpackIter::new(self, Unpacker::default())
+    }
+
+    /// Unpacks the bytes using the provided unpacker, with
+    /// whichever line buffer it was built on.
+    fn meatunpack_with<B: LineBuffer>(
+        self,
+        unpacker: BufferedUnpacker<B>,
+    ) -> UnpackIter<Self, B> {
+        UnpackIter::new(self, unpacker)
+    }
+}
+
+impl<I: Iterator<Item = u8>> MeatPackIterExt for I {}
@@ -1,13 +1,16 @@
+use core::ops::Deref;
+
 use thiserror::Error;
 
 pub static SIGNAL_BYTE: u8 = 255;
 pub static PACKING_ENABLED_BYTE: u8 = 251;
 pub static ENABLE_NO_SPACES: u8 = 247;
 pub static LINEFEED_BYTE: u8 = b'\n';
+pub static CARRIAGE_RETURN_BYTE: u8 = b'\r';
 pub static COMMENT_START_BYTE: u8 = b';';
 pub static FULLWIDTH_BYTE: u8 = 0b0000_1111;
-pub static MEATPACK_HEADER: [u8; 3] = [SIGNAL_BYTE, SIGNAL_BYTE, PACKING_ENABLED_BYTE];
-pub static NO_SPACES_COMMAND: [u8; 3] = [SIGNAL_BYTE, SIGNAL_BYTE, ENABLE_NO_SPACES];
+pub static MEATPACK_HEADER: [u8; 3] = MeatPackCommand::PackingEnabled.to_bytes();
+pub static NO_SPACES_COMMAND: [u8; 3] = MeatPackCommand::NoSpacesEnabled.to_bytes();
 
 /// The pack trait that provide the ability
 /// to pack an item into a 4-bit meatpack
@@ -33,7 +36,10 @@ impl Pack for u8 {
         &self,
         no_spaces: bool,
     ) -> Option<u8> {
-        forward_lookup(self, no_spaces)
+        match PACK_TABLE[usize::from(no_spaces)][usize::from(*self)] {
+            0b1111 => None,
+            code => Some(code),
+        }
     }
 
     fn unpack(
@@ -63,11 +69,127 @@ impl PackTuple for (u8, u8) {
 }
 
 /// Used in the Packer and Unpacker to inform the
-/// user whether a line has been omitted or more
-/// bytes are required.
+/// user whether a line has been omitted, more
+/// bytes are required or a command was received.
 pub enum MeatPackResult<'a> {
     WaitingForNextByte,
     Line(&'a [u8]),
+    /// The start of a line too long for the buffer. The rest of
+    /// the line follows as further `Partial` chunks and a `Line`.
+    Partial(&'a [u8]),
+    Command(MeatPackCommand),
+    /// Decoding picked up again after corrupt data. Holds the
+    /// number of bytes dropped along with the corrupt line.
+    Resynced(usize),
+}
+
+/// What the Packer does with bytes that can't be sent as they
+/// are: `0xFF`, which the unpacker reads as a signal byte, and
+/// `NUL`, which marks a fullwidth character.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReservedBytePolicy {
+    /// Return a `ReservedByte` error.
+    #[default]
+    Error,
+    /// Leave the byte out of the packed gcode.
+    Drop,
+    /// Send the rest of the line as plain text, between a
+    /// `PackingDisabled` and a `PackingEnabled` command. A lone
+    /// `0xFF` in plain text unpacks as itself but two in a row
+    /// would be a command so still return an error.
+    DisablePacking,
+}
+
+/// How closely the Unpacker checks the data it is given
+/// follows the protocol.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strictness {
+    /// Unpack anything that can be made sense of.
+    #[default]
+    Permissive,
+    /// Reject data a well behaved packer would not produce: a
+    /// missing header, fullwidth bytes that could have been
+    /// packed, `NUL`s, stray signal bytes in plain text and an
+    /// unterminated final line. A missing header is reported on
+    /// the first byte only.
+    Strict,
+}
+
+/// The line ending the Unpacker gives its lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    /// Keep the line endings as they were packed.
+    #[default]
+    Preserve,
+    /// End every line with `\n`.
+    Lf,
+    /// End every line with `\r\n`.
+    CrLf,
+}
+
+/// What the Packer and Unpacker do when a line
+/// does not fit in their buffer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Return `MeatPackError::BufferFull` and drop the rest of the line.
+    #[default]
+    Error,
+    /// Hand the line out in chunks with `MeatPackResult::Partial`.
+    Partial,
+    /// Drop the rest of the line and return what fits
+    /// once the line ends.
+    Truncate,
+}
+
+/// A handful of bytes emitted in one go, such as the output for a
+/// single input byte of the `StreamPacker` and `StreamUnpacker`.
+/// It derefs to a slice of the bytes emitted, which may be empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Emitted<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Emitted<N> {
+    /// An empty set of emitted bytes.
+    pub(crate) const fn new() -> Self {
+        Self {
+            buf: [0u8; N],
+            len: 0,
+        }
+    }
+
+    /// Adds a byte. The callers never emit more than `N` bytes.
+    pub(crate) fn push(
+        &mut self,
+        byte: u8,
+    ) {
+        self.buf[self.len] = byte;
+        self.len += 1;
+    }
+
+    /// Returns the emitted bytes.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buf[0..self.len]
+    }
+}
+
+impl<const N: usize> Deref for Emitted<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+/// Reports how far a slice to slice pack or unpack got
+/// before running out of input or output space.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Bytes read from the input slice.
+    pub consumed: usize,
+    /// Bytes written to the output slice.
+    pub written: usize,
 }
 
 /// A set of possible error codes from the MeatPack crate.
@@ -87,10 +209,50 @@ pub enum MeatPackError {
     EmptyBuffer,
     #[error(r"Unterminated buffer. Expected the in buffer to terminate with a \n.")]
     UnterminatedBuffer,
+    #[error("Invalid config report.")]
+    InvalidConfigReport,
+    #[error("Modes can only be switched between lines.")]
+    MidLine,
+    #[error("Invalid command sequence. Expected two signal bytes.")]
+    InvalidCommandSequence,
+    #[error("The data does not start with the MeatPack header.")]
+    MissingHeader,
+    #[error("Fullwidth byte {0} should have been packed.")]
+    UnneededFullwidth(u8),
+    #[error("Unexpected NUL byte.")]
+    NulByte,
+    #[error("Signal byte outside of a command sequence in plain text.")]
+    StraySignalByte,
+    #[error("The data ends part way through a line.")]
+    TrailingPartialLine,
+    #[error("Reserved byte {0} in the gcode.")]
+    ReservedByte(u8),
+    #[error("A completed line has {0} bytes still to be written out.")]
+    UndrainedLine(usize),
+}
+
+/// A `MeatPackError` raised by the Packer or Unpacker along
+/// with where in its input it happened.
+#[derive(Debug, Error)]
+#[error("{error} At line {line}, byte {byte}.")]
+pub struct PositionedError {
+    /// The error raised.
+    pub error: MeatPackError,
+    /// The line, counting from 1, of the input byte. For the
+    /// Unpacker this is the line being unpacked.
+    pub line: usize,
+    /// The offset, counting from 0, of the input byte.
+    pub byte: usize,
+}
+
+impl From<PositionedError> for MeatPackError {
+    fn from(e: PositionedError) -> Self {
+        e.error
+    }
 }
 
 /// An enum detailing all the available Meatpack commands.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MeatPackCommand {
     PackingEnabled,
     PackingDisabled,
@@ -101,6 +263,37 @@ pub enum MeatPackCommand {
     SignalByte,
 }
 
+impl MeatPackCommand {
+    /// The command byte that follows the two signal bytes.
+    pub const fn byte(&self) -> u8 {
+        match self {
+            MeatPackCommand::NoSpacesDisabled => 246,
+            MeatPackCommand::NoSpacesEnabled => ENABLE_NO_SPACES,
+            MeatPackCommand::QueryConfig => 248,
+            MeatPackCommand::ResetAll => 249,
+            MeatPackCommand::PackingDisabled => 250,
+            MeatPackCommand::PackingEnabled => PACKING_ENABLED_BYTE,
+            MeatPackCommand::SignalByte => SIGNAL_BYTE,
+        }
+    }
+
+    /// The full command sequence to send, i.e. the two
+    /// signal bytes followed by the command byte. For `SignalByte`
+    /// this is three signal bytes, which the unpackers take as a
+    /// command that changes nothing.
+    pub const fn to_bytes(&self) -> [u8; 3] {
+        [SIGNAL_BYTE, SIGNAL_BYTE, self.byte()]
+    }
+
+    /// Decodes a full command sequence produced by `to_bytes`.
+    pub const fn from_bytes(bytes: &[u8; 3]) -> Result<Self, MeatPackError> {
+        if !is_signal_byte(&bytes[0]) || !is_signal_byte(&bytes[1]) {
+            return Err(MeatPackError::InvalidCommandSequence);
+        }
+        determine_command(&bytes[2])
+    }
+}
+
 /// This function checks whether a `u8` conforms to one of the reserved
 /// command bytes.
 ///
@@ -139,20 +332,30 @@ pub fn unpack_byte(
     byte: &u8,
     no_spaces: bool,
 ) -> (u8, u8) {
-    // Process the 8-bit as two 4-bit values.
-    // 4-bits still exist within a u8.
-    let mut unpacked: (u8, u8) = (0, 0);
-    // Take the 4 most significant bits.
-    // e.g. 0111_0010 >> 4 -> 0000_0111
-    let most = byte >> 4;
-    let u = reverse_lookup(&most, no_spaces).unwrap();
-    unpacked.0 = u;
-    // Take the 4 least significant bits.
-    // e.g., 0111_0010 << 4 -> 0010_0000 -> 0000_0010
-    let least = byte << 4 >> 4;
-    let u = reverse_lookup(&least, no_spaces).unwrap();
-    unpacked.1 = u;
-    unpacked
+    UNPACK_TABLE[usize::from(no_spaces)][usize::from(*byte)]
+}
+
+/// The (most, least) characters of every packed byte, with `NUL`
+/// for a fullwidth marker, indexed by whether no spaces is enabled.
+pub(crate) static UNPACK_TABLE: [[(u8, u8); 256]; 2] = [unpack_table(false), unpack_table(true)];
+
+/// Builds the characters of every packed byte from `reverse_lookup`.
+const fn unpack_table(no_spaces: bool) -> [(u8, u8); 256] {
+    let mut table = [(0, 0); 256];
+    let mut i = 0;
+    while i < table.len() {
+        // Process the 8-bit as two 4-bit values.
+        // e.g. 0111_0010 -> 0000_0111 and 0000_0010
+        let byte = i as u8;
+        if let (Some(most), Some(least)) = (
+            reverse_lookup(&(byte >> 4), no_spaces),
+            reverse_lookup(&(byte & 0b1111), no_spaces),
+        ) {
+            table[i] = (most, least);
+        }
+        i += 1;
+    }
+    table
 }
 
 /// Provides the lookup table for the 4-bit combinations and their 8-bit counterparts. `0b1011` has different intepretations depending on whether `no_spaces` has been enabled or disabled.
@@ -210,6 +413,23 @@ pub const fn reverse_lookup(
     }
 }
 
+/// The 4-bit code of every byte, `0b1111` for those sent
+/// fullwidth, indexed by whether no spaces is enabled.
+pub(crate) static PACK_TABLE: [[u8; 256]; 2] = [pack_table(false), pack_table(true)];
+
+/// Builds the 4-bit code of every byte from `forward_lookup`.
+const fn pack_table(no_spaces: bool) -> [u8; 256] {
+    let mut table = [0b1111; 256];
+    let mut i = 0;
+    while i < table.len() {
+        if let Some(code) = forward_lookup(&(i as u8), no_spaces) {
+            table[i] = code;
+        }
+        i += 1;
+    }
+    table
+}
+
 /// The forward lookup variant of the reverse lookup byte.
 pub const fn forward_lookup(
     byte: &u8,
@@ -267,6 +487,35 @@ mod test {
         assert_eq!(packed, 0u8);
     }
 
+    #[test]
+    fn test_command_round_trip() {
+        for cmd in [
+            MeatPackCommand::PackingEnabled,
+            MeatPackCommand::PackingDisabled,
+            MeatPackCommand::ResetAll,
+            MeatPackCommand::QueryConfig,
+            MeatPackCommand::NoSpacesEnabled,
+            MeatPackCommand::NoSpacesDisabled,
+            MeatPackCommand::SignalByte,
+        ] {
+            assert_eq!(MeatPackCommand::from_bytes(&cmd.to_bytes()).unwrap(), cmd);
+            assert_eq!(determine_command(&cmd.byte()).unwrap(), cmd);
+
+            // Every sequence can be sent to an unpacker.
+            let mut unpacker = crate::Unpacker::<8>::default();
+            let [first, second, third] = cmd.to_bytes();
+            unpacker.unpack(&first).unwrap();
+            unpacker.unpack(&second).unwrap();
+            assert!(matches!(
+                unpacker.unpack(&third),
+                Ok(MeatPackResult::Command(c)) if c == cmd
+            ));
+        }
+        assert_eq!(MEATPACK_HEADER, [255, 255, 251]);
+        assert!(MeatPackCommand::from_bytes(&[255, 0, 251]).is_err());
+        assert!(MeatPackCommand::from_bytes(&[255, 255, 0]).is_err());
+    }
+
     #[test]
     fn test_pack_unpackable() {
         let unpackable: u8 = b'T';
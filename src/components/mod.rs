@@ -1,5 +1,16 @@
+pub(crate) mod buffer;
+#[cfg(feature = "alloc")]
+pub(crate) mod bulk;
+pub(crate) mod config;
+pub(crate) mod detect;
+#[cfg(feature = "std")]
+pub(crate) mod io;
+pub(crate) mod iter;
 pub(crate) mod meat;
 pub(crate) mod pack;
+pub(crate) mod parallel;
+pub(crate) mod stream_pack;
+pub(crate) mod stream_unpack;
 #[cfg(test)]
 mod tests;
 pub(crate) mod unpack;
@@ -1,208 +1,267 @@
+use crate::components::buffer::LineBuffer;
 use crate::components::meat::{
-    COMMENT_START_BYTE, FULLWIDTH_BYTE, LINEFEED_BYTE, MeatPackError, MeatPackResult, Pack,
-    PackTuple, forward_lookup,
+    LINEFEED_BYTE, MEATPACK_HEADER, MeatPackCommand, MeatPackError, MeatPackResult,
+    NO_SPACES_COMMAND, OverflowPolicy, PositionedError, Progress, ReservedBytePolicy,
 };
+use crate::components::stream_pack::StreamPacker;
 
-#[cfg(feature = "alloc")]
-use crate::MEATPACK_HEADER;
 #[cfg(feature = "alloc")]
 use alloc::vec::Vec;
 
+/// A packer whose line capacity is fixed at compile time.
+pub type Packer<const S: usize> = BufferedPacker<[u8; S]>;
+
+/// A packer whose line buffer is allocated at runtime
+/// and grows to fit the longest line.
+#[cfg(feature = "alloc")]
+pub type VecPacker = BufferedPacker<Vec<u8>>;
+
 /// A  struct for that packs bytes and emits
 /// lines of meatpacked gcode. Stripping comments
 /// is on by default and empty lines are omitted.
-pub struct Packer<const S: usize> {
-    least: Option<u8>,
-    fullwidth: Option<u8>,
+/// It buffers the output of a `StreamPacker` into lines
+/// held in a `LineBuffer`. Lines longer than the buffer
+/// are handled according to its `OverflowPolicy`.
+pub struct BufferedPacker<B: LineBuffer> {
+    stream: StreamPacker,
+    overflow: OverflowPolicy,
+    truncating: bool,
+    overflowed: bool,
     clear: bool,
-    strip_whitespace: bool,
-    strip_comments: bool,
-    comment_flag: bool,
     pos: usize,
-    inner: [u8; S],
+    inner: B,
+    header_bytes: Option<([u8; 6], usize)>,
+    header_pos: usize,
+    drain: usize,
+    lines: usize,
+    bytes: usize,
 }
 
 impl<const S: usize> Default for Packer<S> {
     /// The default implementation of a Packer.
     fn default() -> Self {
-        Self {
-            least: None,
-            fullwidth: None,
-            clear: false,
-            strip_whitespace: false,
-            strip_comments: true,
-            comment_flag: false,
-            pos: 0,
-            inner: [0u8; S],
-        }
+        Self::with_buffer([0u8; S], true, false)
     }
 }
 
-impl<const S: usize> Packer<S> {
-    /// Create a new instance of the packer
-    pub fn new(
+#[cfg(feature = "alloc")]
+impl Default for VecPacker {
+    /// The default implementation of a VecPacker.
+    fn default() -> Self {
+        Self::with_buffer(Vec::new(), true, false)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl VecPacker {
+    /// Create a new instance of the packer with room for
+    /// `capacity` bytes of a line to start with.
+    pub fn with_capacity(
+        capacity: usize,
+        strip_comments: bool,
+        strip_whitespace: bool,
+    ) -> Self {
+        Self::with_buffer(alloc::vec![0u8; capacity], strip_comments, strip_whitespace)
+    }
+}
+
+impl<B: LineBuffer> BufferedPacker<B> {
+    /// Create a new instance of the packer that builds
+    /// its lines in `buffer`.
+    pub fn with_buffer(
+        buffer: B,
         strip_comments: bool,
         strip_whitespace: bool,
     ) -> Self {
         Self {
-            least: None,
-            fullwidth: None,
+            stream: StreamPacker::new(strip_comments, strip_whitespace),
+            overflow: OverflowPolicy::default(),
+            truncating: false,
+            overflowed: false,
             clear: false,
-            strip_whitespace,
-            strip_comments,
-            comment_flag: false,
             pos: 0,
-            inner: [0u8; S],
+            inner: buffer,
+            header_bytes: None,
+            header_pos: 0,
+            drain: 0,
+            lines: 0,
+            bytes: 0,
         }
     }
 
-    /// Pack a byte into the current line.
+    /// Sets what happens when a packed line does not fit in
+    /// the buffer.
+    pub fn with_overflow(
+        mut self,
+        overflow: OverflowPolicy,
+    ) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Packs the gcode exactly as it is, keeping comments,
+    /// whitespace and empty lines, so that a lossless unpacker
+    /// gives back the same bytes. This turns stripping off.
+    pub fn with_lossless(
+        mut self,
+        lossless: bool,
+    ) -> Self {
+        self.stream = self.stream.with_lossless(lossless);
+        self
+    }
+
+    /// Packs `\r\n` line endings as `\n`, saving a fullwidth
+    /// byte on every line.
+    pub fn with_normalize_crlf(
+        mut self,
+        normalize_crlf: bool,
+    ) -> Self {
+        self.stream = self.stream.with_normalize_crlf(normalize_crlf);
+        self
+    }
+
+    /// Sets what happens to `0xFF` and `NUL` bytes in the gcode.
+    pub fn with_reserved_bytes(
+        mut self,
+        reserved: ReservedBytePolicy,
+    ) -> Self {
+        self.stream = self.stream.with_reserved_bytes(reserved);
+        self
+    }
+
+    /// Pack a byte into the current line. Errors carry the
+    /// position of the byte in the gcode. Errors with
+    /// `UndrainedLine` while `pack_into` holds a completed line.
     pub fn pack(
         &mut self,
         b: &u8,
-    ) -> Result<MeatPackResult, MeatPackError> {
+    ) -> Result<MeatPackResult<'_>, PositionedError> {
+        let (line, byte) = (self.line_number(), self.bytes);
+        self.check_drained()?;
+        self.bytes += 1;
+        self.pack_byte(b)
+            .map_err(|error| PositionedError { error, line, byte })
+    }
+
+    /// The line, counting from 1, the next byte of gcode is on.
+    pub fn line_number(&self) -> usize {
+        self.lines + 1
+    }
+
+    /// The number of bytes of gcode packed so far, which is
+    /// the offset of the next byte.
+    pub fn byte_offset(&self) -> usize {
+        self.bytes
+    }
+
+    /// Packs a byte into the current line.
+    fn pack_byte(
+        &mut self,
+        b: &u8,
+    ) -> Result<MeatPackResult<'_>, MeatPackError> {
         // Cleat the buffer if we have been instructed to do so.
         if self.clear {
             self.clear()
         }
-        // Ignore whitespace if we have been instructed to do so.
-        if self.strip_whitespace && [b' ', b'\t'].contains(b) {
-            return Ok(MeatPackResult::WaitingForNextByte);
+        let line_end = *b == LINEFEED_BYTE;
+        if line_end {
+            self.lines += 1;
         }
-        // Check if strip comments is active and ignore
-        if self.strip_comments {
-            if *b == COMMENT_START_BYTE {
-                self.comment_flag = true;
-            }
-            if *b == LINEFEED_BYTE {
-                self.comment_flag = false;
-            }
-            if self.comment_flag {
-                return Ok(MeatPackResult::WaitingForNextByte);
+        // Skip the rest of a line that did not fit.
+        if self.overflowed {
+            if line_end {
+                self.overflowed = false;
+                self.stream.discard_line();
             }
+            return Ok(MeatPackResult::WaitingForNextByte);
         }
-
-        // Match on the possible two bytes we have. One that is intended for the least and most significant ends of a u8.
-        match (self.least, b) {
-            // Special case requiring \n\n.
-            (None, b'\n') => {
-                let most = b'\n'
-                    .pack(self.strip_whitespace)
-                    .expect(r"Expect \n to return 0b0000_1100");
-                let least = b'\n'
-                    .pack(self.strip_whitespace)
-                    .expect(r"Expect \n to return 0b0000_1100");
-                let packed_byte = (most, least)
-                    .pack()
-                    .expect("Should pack as we have provided to two known packables.");
-                self.push(packed_byte)?;
-                self.clear = true;
-                // Remove empty lines.
-                if self.pos > 1 {
-                    Ok(MeatPackResult::Line(self.return_slice()))
-                } else {
-                    Ok(MeatPackResult::WaitingForNextByte)
-                }
-            }
-            // Start of a new byte to pack.
-            (None, b) => match b.pack(self.strip_whitespace) {
-                // Packable byte
-                Some(least) => {
-                    self.least = Some(least);
-                    self.fullwidth = None;
-                    Ok(MeatPackResult::WaitingForNextByte)
-                }
-                // Fullwidth byte
-                None => {
-                    self.least = Some(0b1111);
-                    self.fullwidth = Some(*b);
-                    Ok(MeatPackResult::WaitingForNextByte)
-                }
-            },
-            // fullwidth + \n
-            (Some(0b1111), b'\n') => {
-                let most = b'\n'
-                    .pack(self.strip_whitespace)
-                    .expect(r"Expected \n to return 0b0000_1100");
-                let packed_byte = (most, FULLWIDTH_BYTE)
-                    .pack()
-                    .expect("Should pack as we have provided to two packed chars.");
-                self.push(packed_byte)?;
-                self.push(self.fullwidth.unwrap())?;
-                self.least = None;
-                self.fullwidth = None;
+        // Skip the rest of a line being truncated up to its \n.
+        if self.truncating && !line_end {
+            return Ok(MeatPackResult::WaitingForNextByte);
+        }
+        let kept = (self.overflow == OverflowPolicy::Truncate && !self.truncating)
+            .then(|| self.stream.clone());
+        let emitted = self.stream.pack(b)?;
+        self.store(&emitted, line_end)?;
+        // Put the stream back to the end of the kept part of a line
+        // that has just started to be truncated.
+        if let Some(kept) = kept.filter(|_| self.truncating) {
+            self.stream = kept;
+            self.stream.truncate_line();
+            return Ok(MeatPackResult::WaitingForNextByte);
+        }
+        // The line is complete once the \n has been packed.
+        // Empty lines produce no bytes and are omitted.
+        if line_end {
+            self.truncating = false;
+            if self.pos > 0 {
                 self.clear = true;
-                Ok(MeatPackResult::Line(self.return_slice()))
+                return Ok(MeatPackResult::Line(self.return_slice()));
             }
-            // Full width + some other b byte that is not a \n
-            (Some(0b1111), b) => match forward_lookup(b, self.strip_whitespace) {
-                // Packable byte
-                Some(most) => {
-                    let packed_byte = (most, FULLWIDTH_BYTE)
-                        .pack()
-                        .expect("Should pack as we have provided to two packed chars.");
-                    self.push(packed_byte)?;
-                    self.push(self.fullwidth.unwrap())?;
-                    self.least = None;
-                    self.fullwidth = None;
-                    Ok(MeatPackResult::WaitingForNextByte)
-                }
-                // Fullwidth byte
-                None => {
-                    // Equivalent to a SIGNAL BYTE but keeping the function for
-                    // readability.
-                    let packed_byte = (FULLWIDTH_BYTE, FULLWIDTH_BYTE)
-                        .pack()
-                        .expect("Should pack as we have provided to two packed chars.");
-                    self.push(packed_byte)?;
-                    self.push(self.fullwidth.unwrap())?;
-                    self.push(*b)?;
-                    self.least = None;
-                    self.fullwidth = None;
-                    Ok(MeatPackResult::WaitingForNextByte)
-                }
-            },
-            // Some packable least byte with a \n most.
-            (Some(least), b'\n') => {
-                let most = b.pack(self.strip_whitespace).expect("Should be packable.");
-                let packed_bytes = (most, least).pack().expect("Should be packable.");
-                self.push(packed_bytes)?;
-                self.least = None;
-                self.fullwidth = None;
-                self.clear = true;
-                Ok(MeatPackResult::Line(self.return_slice()))
+        }
+        // Hand out what we have if the next byte might not fit.
+        if self.overflow == OverflowPolicy::Partial
+            && self.pos > 0
+            && !self.inner.make_room(self.pos + self.stream.emitted_max().0)
+        {
+            self.clear = true;
+            return Ok(MeatPackResult::Partial(self.return_slice()));
+        }
+        Ok(MeatPackResult::WaitingForNextByte)
+    }
+
+    /// Adds the bytes emitted for a single input byte to the inner.
+    /// When truncating, the bytes are kept or dropped together so
+    /// the packed line remains valid, and room is always left for
+    /// the bytes that end the line. Otherwise a line that does
+    /// not fit is dropped and the rest of it skipped.
+    fn store(
+        &mut self,
+        emitted: &[u8],
+        line_end: bool,
+    ) -> Result<(), MeatPackError> {
+        let line_end_max = self.stream.emitted_max().1;
+        if self.overflow == OverflowPolicy::Truncate
+            && !line_end
+            && (self.truncating
+                || !self
+                    .inner
+                    .make_room(self.pos + emitted.len() + line_end_max))
+        {
+            self.truncating = true;
+            return Ok(());
+        }
+        for byte in emitted {
+            if let Err(e) = self.push(*byte) {
+                self.pos = 0;
+                self.overflowed = !line_end;
+                return Err(e);
             }
-            // least is packable + whatever b is but not a \n
-            (Some(least), b) => match b.pack(self.strip_whitespace) {
-                // Packable byte
-                Some(most) => {
-                    let packed_byte = (most, least).pack().expect("Should be packable.");
-                    self.push(packed_byte)?;
-                    self.least = None;
-                    self.fullwidth = None;
-                    Ok(MeatPackResult::WaitingForNextByte)
-                }
-                // Fullwidth byte
-                None => {
-                    let packed_byte = (FULLWIDTH_BYTE, least).pack().expect("Should be packable.");
-                    self.push(packed_byte)?;
-                    self.push(*b)?;
-                    self.least = None;
-                    self.fullwidth = None;
-                    Ok(MeatPackResult::WaitingForNextByte)
-                }
-            },
         }
+        Ok(())
+    }
+
+    /// Errors if a completed line is still to be written out by
+    /// `pack_into`, as packing on would write over it.
+    fn check_drained(&self) -> Result<(), PositionedError> {
+        if self.drain > 0 {
+            return Err(PositionedError {
+                error: MeatPackError::UndrainedLine(self.drain),
+                line: self.line_number(),
+                byte: self.bytes,
+            });
+        }
+        Ok(())
     }
 
     /// Returns a slice of the filled elements in the inner.
     fn return_slice(&mut self) -> &[u8] {
-        &self.inner[0..self.pos]
+        &self.inner.as_bytes()[0..self.pos]
     }
 
-    /// Clears the inner
+    /// Clears the inner. Only the first `pos` bytes are ever
+    /// read so the old line is left to be written over.
     fn clear(&mut self) {
-        self.inner.fill(0);
         self.pos = 0;
         self.clear = false;
     }
@@ -212,22 +271,195 @@ impl<const S: usize> Packer<S> {
         &mut self,
         byte: u8,
     ) -> Result<(), MeatPackError> {
-        if self.pos > S {
+        if !self.inner.make_room(self.pos + 1) {
             return Err(MeatPackError::BufferFull);
         }
-        self.inner[self.pos] = byte;
+        self.inner.as_bytes_mut()[self.pos] = byte;
         self.pos += 1;
         Ok(())
     }
 
+    /// The number of packed bytes held for the current line
+    /// that have not yet been returned, including any pending
+    /// half-packed character.
+    pub(crate) fn pending(&self) -> usize {
+        let buffered = if self.clear { 0 } else { self.pos };
+        buffered + self.stream.pending()
+    }
+
     /// A utility function to check if any data remains
     /// in the internal inner. We expect all meatpack
-    /// lines to newline end.
+    /// lines to newline end, otherwise call `finish`.
     pub fn data_remains(&self) -> bool {
-        if !self.clear || self.pos == 0 {
-            return true;
+        self.pending() > 0
+    }
+
+    /// Flushes whatever remains of an unterminated final line,
+    /// including a character still waiting for a partner, and
+    /// returns it. With `append_newline` the line is terminated
+    /// with a `\n` as if the gcode had ended with one. Returns
+    /// `None` if the last line was already complete. Errors with
+    /// `UndrainedLine` while `pack_into` holds a completed line.
+    pub fn finish(
+        &mut self,
+        append_newline: bool,
+    ) -> Result<Option<&[u8]>, PositionedError> {
+        let (line, byte) = (self.line_number(), self.bytes);
+        self.check_drained()?;
+        self.finish_line(append_newline)
+            .map_err(|error| PositionedError { error, line, byte })
+    }
+
+    /// Flushes the final line.
+    fn finish_line(
+        &mut self,
+        append_newline: bool,
+    ) -> Result<Option<&[u8]>, MeatPackError> {
+        if self.clear {
+            self.clear()
+        }
+        if self.overflowed {
+            self.overflowed = false;
+            self.stream.discard_line();
+            return Ok(None);
+        }
+        if append_newline && self.stream.mid_line() {
+            return match self.pack_byte(&LINEFEED_BYTE)? {
+                MeatPackResult::Line(line) => Ok(Some(line)),
+                _ => Ok(None),
+            };
         }
-        false
+        let emitted = self.stream.finish()?;
+        self.store(&emitted, true)?;
+        self.truncating = false;
+        if self.pos == 0 {
+            return Ok(None);
+        }
+        self.clear = true;
+        Ok(Some(self.return_slice()))
+    }
+
+    /// Switches to packing from the next line, returning the
+    /// command bytes to send ahead of it. Errors if part of a
+    /// line has been packed.
+    pub fn enable_packing(&mut self) -> Result<[u8; 3], MeatPackError> {
+        self.stream.enable_packing()
+    }
+
+    /// Switches to sending plain text from the next line, returning
+    /// the command bytes to send ahead of it. Errors if part of a
+    /// line has been packed.
+    pub fn disable_packing(&mut self) -> Result<[u8; 3], MeatPackError> {
+        self.stream.disable_packing()
+    }
+
+    /// Switches to the no spaces lookup from the next line, returning
+    /// the command bytes to send ahead of it. Errors if part of a
+    /// line has been packed.
+    pub fn enable_no_spaces(&mut self) -> Result<[u8; 3], MeatPackError> {
+        self.stream.enable_no_spaces()
+    }
+
+    /// Switches back to the standard lookup from the next line,
+    /// returning the command bytes to send ahead of it. Errors if
+    /// part of a line has been packed.
+    pub fn disable_no_spaces(&mut self) -> Result<[u8; 3], MeatPackError> {
+        self.stream.disable_no_spaces()
+    }
+
+    /// The header expected ahead of the packed lines given
+    /// the current mode of the packer.
+    pub(crate) fn header(&self) -> ([u8; 6], usize) {
+        let mut header = [0u8; 6];
+        if self.stream.packing() {
+            header[0..3].copy_from_slice(&MEATPACK_HEADER);
+        } else {
+            header[0..3].copy_from_slice(&MeatPackCommand::PackingDisabled.to_bytes());
+        }
+        if self.stream.no_spaces() {
+            header[3..6].copy_from_slice(&NO_SPACES_COMMAND);
+            return (header, 6);
+        }
+        (header, 3)
+    }
+
+    /// Packs as much of `in_buf` as will fit into `out_buf` without
+    /// allocating, writing the header ahead of the first line. The
+    /// header is taken from the mode of the packer on the first call,
+    /// so switching modes later only sends the command bytes once.
+    ///
+    /// Completed lines that do not fit are held by the packer and
+    /// written out first on the next call, so the call can be resumed
+    /// with `&in_buf[progress.consumed..]` and a fresh output buffer.
+    /// Pass an empty `in_buf` to drain what is left. Lines that are
+    /// not yet terminated remain in the packer until `finish_into`.
+    pub fn pack_into(
+        &mut self,
+        in_buf: &[u8],
+        out_buf: &mut [u8],
+    ) -> Result<Progress, PositionedError> {
+        let mut progress = Progress::default();
+
+        if self.header_bytes.is_none() {
+            self.header_bytes = Some(self.header());
+        }
+        let (header, len) = self.header_bytes.unwrap_or_default();
+        while self.header_pos < len {
+            if progress.written == out_buf.len() {
+                return Ok(progress);
+            }
+            out_buf[progress.written] = header[self.header_pos];
+            progress.written += 1;
+            self.header_pos += 1;
+        }
+
+        loop {
+            // Write out what remains of the last completed line.
+            if self.drain > 0 {
+                let line = &self.inner.as_bytes()[self.pos - self.drain..self.pos];
+                let n = line.len().min(out_buf.len() - progress.written);
+                out_buf[progress.written..progress.written + n].copy_from_slice(&line[..n]);
+                progress.written += n;
+                self.drain -= n;
+                if self.drain > 0 {
+                    return Ok(progress);
+                }
+            }
+            if progress.consumed == in_buf.len() {
+                return Ok(progress);
+            }
+            if let MeatPackResult::Line(line) | MeatPackResult::Partial(line) =
+                self.pack(&in_buf[progress.consumed])?
+            {
+                self.drain = line.len();
+            }
+            progress.consumed += 1;
+        }
+    }
+
+    /// Writes out what `pack_into` holds followed by an unterminated
+    /// final line, returning the number of bytes written. Call it
+    /// with a fresh output buffer until it returns 0.
+    pub fn finish_into(
+        &mut self,
+        out_buf: &mut [u8],
+    ) -> Result<usize, PositionedError> {
+        let mut written = self.pack_into(&[], out_buf)?.written;
+        if self.drain == 0 {
+            self.drain = self.finish(false)?.map_or(0, <[u8]>::len);
+            written += self.pack_into(&[], &mut out_buf[written..])?.written;
+        }
+        Ok(written)
+    }
+}
+
+impl<const S: usize> Packer<S> {
+    /// Create a new instance of the packer
+    pub fn new(
+        strip_comments: bool,
+        strip_whitespace: bool,
+    ) -> Self {
+        Self::with_buffer([0u8; S], strip_comments, strip_whitespace)
     }
 
     /// A convenience function for those with alloc available to them.
@@ -239,16 +471,10 @@ impl<const S: usize> Packer<S> {
         strip_comments: bool,
         strip_whitespace: bool,
     ) -> Result<(), MeatPackError> {
-        use super::meat::NO_SPACES_COMMAND;
-
         if in_buf.is_empty() {
             return Err(MeatPackError::EmptyBuffer);
         }
 
-        if in_buf.last().unwrap() != &b'\n' {
-            return Err(MeatPackError::UnterminatedBuffer);
-        }
-
         out_buf.extend(MEATPACK_HEADER.as_slice());
         if strip_whitespace {
             out_buf.extend(NO_SPACES_COMMAND.as_slice());
@@ -258,18 +484,18 @@ impl<const S: usize> Packer<S> {
 
         for b in in_buf {
             match packer.pack(b) {
-                Ok(MeatPackResult::Line(line)) => out_buf.extend(line),
+                Ok(MeatPackResult::Line(line) | MeatPackResult::Partial(line)) => {
+                    out_buf.extend(line)
+                }
                 Ok(MeatPackResult::WaitingForNextByte) => {}
-                Err(e) => return Err(e),
+                Ok(MeatPackResult::Command(_)) => {}
+                Ok(MeatPackResult::Resynced(_)) => {}
+                Err(e) => return Err(e.error),
             }
         }
-        // if the packer is in the state of clearing itself
-        // on the next iteration then ignore as we hit a new line.
-        // Otherwise we have an unterminated line with some
-        // data possibly stuck in the inner and we're expecting
-        // to end with a terminated line so throw an err.
-        if packer.data_remains() {
-            return Err(MeatPackError::UnterminatedLine(packer.pos));
+        // Pick up a final line that is not terminated.
+        if let Some(line) = packer.finish(false)? {
+            out_buf.extend(line);
         }
 
         Ok(())
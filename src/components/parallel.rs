@@ -0,0 +1,150 @@
+use crate::components::meat::LINEFEED_BYTE;
+
+#[cfg(feature = "std")]
+use crate::components::{
+    buffer::LineBuffer,
+    meat::{MeatPackError, MeatPackResult, PositionedError},
+    pack::BufferedPacker,
+};
+#[cfg(feature = "std")]
+use std::{num::NonZeroUsize, panic, thread, vec::Vec};
+
+/// An iterator over pieces of gcode that end at a line boundary,
+/// from `line_chunks`.
+pub struct LineChunks<'a> {
+    rest: &'a [u8],
+    target: usize,
+}
+
+/// Splits gcode into about `chunks` pieces of a similar size. Every
+/// piece but the last ends with a newline so, once the header and
+/// mode are fixed, each can be packed on its own and the packed
+/// pieces joined in order.
+pub fn line_chunks(
+    in_buf: &[u8],
+    chunks: usize,
+) -> LineChunks<'_> {
+    LineChunks {
+        rest: in_buf,
+        target: in_buf.len().div_ceil(chunks.max(1)).max(1),
+    }
+}
+
+impl<'a> Iterator for LineChunks<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        // Carry on to the end of the line the target falls in.
+        let start = self.target.min(self.rest.len()) - 1;
+        let end = self.rest[start..]
+            .iter()
+            .position(|b| *b == LINEFEED_BYTE)
+            .map_or(self.rest.len(), |p| start + p + 1);
+        let (chunk, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Some(chunk)
+    }
+}
+
+/// Packs a whole slice of gcode across `jobs` threads, or one per
+/// CPU if `jobs` is 0. The gcode is split with `line_chunks` and
+/// each piece is packed by its own packer from `new_packer`, so
+/// they must all be set up the same way. The header for that setup
+/// and the packed pieces are written to `out_buf` in order, giving
+/// the same bytes as packing the gcode with a single packer. With
+/// `append_newline` an unterminated final line is terminated, as
+/// with `finish`. Returns the number of lines packed.
+///
+/// Errors carry their position in the whole of the gcode. If more
+/// than one piece fails, the error from the earliest is returned.
+#[cfg(feature = "std")]
+pub fn pack_parallel<B, F>(
+    in_buf: &[u8],
+    out_buf: &mut Vec<u8>,
+    jobs: usize,
+    append_newline: bool,
+    new_packer: F,
+) -> Result<usize, PositionedError>
+where
+    B: LineBuffer,
+    F: Fn() -> BufferedPacker<B> + Sync,
+{
+    if in_buf.is_empty() {
+        return Err(PositionedError {
+            error: MeatPackError::EmptyBuffer,
+            line: 1,
+            byte: 0,
+        });
+    }
+    let jobs = match jobs {
+        0 => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        jobs => jobs,
+    };
+
+    let (header, len) = new_packer().header();
+    out_buf.extend(&header[..len]);
+
+    thread::scope(|scope| {
+        let new_packer = &new_packer;
+        let mut chunks = line_chunks(in_buf, jobs).peekable();
+        let mut workers: Vec<_> = Vec::new();
+        while let Some(chunk) = chunks.next() {
+            // Only the last piece can end part way through a line.
+            let append_newline = append_newline && chunks.peek().is_none();
+            workers.push((
+                chunk.len(),
+                scope.spawn(move || pack_chunk(new_packer(), chunk, append_newline)),
+            ));
+        }
+
+        let (mut packed_lines, mut lines, mut bytes) = (0, 0, 0);
+        for (len, worker) in workers {
+            match worker.join().unwrap_or_else(|e| panic::resume_unwind(e)) {
+                Ok((packed, chunk_packed_lines, chunk_lines)) => {
+                    out_buf.extend(packed);
+                    packed_lines += chunk_packed_lines;
+                    lines += chunk_lines;
+                    bytes += len;
+                }
+                Err(e) => {
+                    return Err(PositionedError {
+                        error: e.error,
+                        line: lines + e.line,
+                        byte: bytes + e.byte,
+                    });
+                }
+            }
+        }
+        Ok(packed_lines)
+    })
+}
+
+/// Packs one piece of gcode, returning the packed bytes, the
+/// number of lines packed and the number of lines it held.
+#[cfg(feature = "std")]
+fn pack_chunk<B: LineBuffer>(
+    mut packer: BufferedPacker<B>,
+    chunk: &[u8],
+    append_newline: bool,
+) -> Result<(Vec<u8>, usize, usize), PositionedError> {
+    let mut out: Vec<u8> = Vec::with_capacity(chunk.len() / 2);
+    let mut packed_lines = 0;
+    for b in chunk {
+        match packer.pack(b)? {
+            MeatPackResult::Line(line) => {
+                packed_lines += 1;
+                out.extend(line);
+            }
+            MeatPackResult::Partial(line) => out.extend(line),
+            _ => {}
+        }
+    }
+    if let Some(line) = packer.finish(append_newline)? {
+        packed_lines += 1;
+        out.extend(line);
+    }
+    Ok((out, packed_lines, packer.line_number() - 1))
+}
@@ -0,0 +1,435 @@
+use crate::components::meat::{
+    CARRIAGE_RETURN_BYTE, COMMENT_START_BYTE, Emitted, FULLWIDTH_BYTE, LINEFEED_BYTE,
+    MeatPackCommand, MeatPackError, Pack, PackTuple, ReservedBytePolicy, SIGNAL_BYTE,
+    forward_lookup, reverse_lookup,
+};
+
+/// A packer that holds no line buffer. Each packed byte (and each
+/// fullwidth byte) is emitted as soon as it is determined so the
+/// only state held is the character waiting for a partner.
+/// Stripping comments is on by default and empty lines are omitted,
+/// matching the `Packer`. Stripping whitespace also enables the
+/// no spaces lookup.
+#[derive(Clone)]
+pub struct StreamPacker {
+    least: Option<u8>,
+    fullwidth: Option<u8>,
+    packing: bool,
+    no_spaces: bool,
+    lossless: bool,
+    normalize_crlf: bool,
+    pending_cr: bool,
+    reserved: ReservedBytePolicy,
+    escaped: bool,
+    signal_sent: bool,
+    strip_whitespace: bool,
+    strip_comments: bool,
+    comment_flag: bool,
+    line_empty: bool,
+}
+
+impl Default for StreamPacker {
+    /// The default implementation of a StreamPacker.
+    fn default() -> Self {
+        Self::new(true, false)
+    }
+}
+
+impl StreamPacker {
+    /// Create a new instance of the stream packer
+    pub fn new(
+        strip_comments: bool,
+        strip_whitespace: bool,
+    ) -> Self {
+        Self {
+            least: None,
+            fullwidth: None,
+            packing: true,
+            no_spaces: strip_whitespace,
+            lossless: false,
+            normalize_crlf: false,
+            pending_cr: false,
+            reserved: ReservedBytePolicy::default(),
+            escaped: false,
+            signal_sent: false,
+            strip_whitespace,
+            strip_comments,
+            comment_flag: false,
+            line_empty: true,
+        }
+    }
+
+    /// Packs the gcode exactly as it is so unpacking with a lossless
+    /// unpacker gives back the same bytes. Comments, whitespace and
+    /// empty lines are all kept, which turns stripping off.
+    pub fn with_lossless(
+        mut self,
+        lossless: bool,
+    ) -> Self {
+        self.lossless = lossless;
+        if lossless {
+            self.strip_comments = false;
+            self.strip_whitespace = false;
+        }
+        self
+    }
+
+    /// Packs `\r\n` line endings as `\n`, saving the fullwidth
+    /// byte the `\r` would otherwise cost on every line. A `\r`
+    /// is held until the next byte shows whether it ends a line.
+    pub fn with_normalize_crlf(
+        mut self,
+        normalize_crlf: bool,
+    ) -> Self {
+        self.normalize_crlf = normalize_crlf;
+        self
+    }
+
+    /// Sets what happens to `0xFF` and `NUL` bytes in the gcode.
+    pub fn with_reserved_bytes(
+        mut self,
+        reserved: ReservedBytePolicy,
+    ) -> Self {
+        self.reserved = reserved;
+        self
+    }
+
+    /// Pack a byte returning the bytes that are now ready to send.
+    /// Usually at most three bytes are emitted: a packed byte and
+    /// up to two fullwidth bytes. Switching a line to plain text
+    /// because of a reserved byte emits more.
+    pub fn pack(
+        &mut self,
+        b: &u8,
+    ) -> Result<Emitted<7>, MeatPackError> {
+        if !self.normalize_crlf {
+            return self.pack_byte(b);
+        }
+        let mut out = Emitted::new();
+        // A held \r that does not end the line is packed after all.
+        if self.pending_cr && *b != LINEFEED_BYTE {
+            for c in self.pack_byte(&CARRIAGE_RETURN_BYTE)?.iter() {
+                out.push(*c);
+            }
+        }
+        self.pending_cr = *b == CARRIAGE_RETURN_BYTE;
+        if !self.pending_cr {
+            for c in self.pack_byte(b)?.iter() {
+                out.push(*c);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Packs a single byte of the gcode.
+    fn pack_byte(
+        &mut self,
+        b: &u8,
+    ) -> Result<Emitted<7>, MeatPackError> {
+        let mut out = Emitted::new();
+        // Ignore whitespace if we have been instructed to do so.
+        if self.strip_whitespace && [b' ', b'\t'].contains(b) {
+            return Ok(out);
+        }
+        // Check if strip comments is active and ignore
+        if self.strip_comments {
+            if *b == COMMENT_START_BYTE {
+                self.comment_flag = true;
+            }
+            if *b == LINEFEED_BYTE {
+                self.comment_flag = false;
+            }
+            if self.comment_flag {
+                return Ok(out);
+            }
+        }
+
+        if [SIGNAL_BYTE, 0].contains(b) {
+            match self.reserved {
+                ReservedBytePolicy::Error => return Err(MeatPackError::ReservedByte(*b)),
+                ReservedBytePolicy::Drop => return Ok(out),
+                ReservedBytePolicy::DisablePacking => {
+                    if *b == SIGNAL_BYTE && self.signal_sent {
+                        return Err(MeatPackError::ReservedByte(*b));
+                    }
+                    // Send the rest of the line as plain text
+                    // starting with any held character.
+                    if self.packing && !self.escaped {
+                        for c in MeatPackCommand::PackingDisabled.to_bytes() {
+                            out.push(c);
+                        }
+                        if let Some(c) = self.held_char() {
+                            out.push(c);
+                        }
+                        self.least = None;
+                        self.fullwidth = None;
+                        self.escaped = true;
+                    }
+                }
+            }
+        }
+
+        // Pass the line through as plain text when packing is disabled.
+        if !self.packing || self.escaped {
+            // Remove empty lines unless lossless.
+            if *b != LINEFEED_BYTE || !self.line_empty || self.lossless {
+                out.push(*b);
+            }
+            self.signal_sent = *b == SIGNAL_BYTE;
+            self.line_empty = *b == LINEFEED_BYTE;
+            // Go back to packing once the line is done.
+            if self.escaped && *b == LINEFEED_BYTE {
+                for c in MeatPackCommand::PackingEnabled.to_bytes() {
+                    out.push(c);
+                }
+                self.escaped = false;
+            }
+            return Ok(out);
+        }
+
+        // Match on the possible two bytes we have. One that is intended for the least and most significant ends of a u8.
+        match (self.least, b) {
+            // Special case requiring \n\n.
+            (None, b'\n') => {
+                // Remove empty lines unless lossless.
+                if !self.line_empty || self.lossless {
+                    let most = b'\n'
+                        .pack(self.no_spaces)
+                        .expect(r"Expect \n to return 0b0000_1100");
+                    let least = b'\n'
+                        .pack(self.no_spaces)
+                        .expect(r"Expect \n to return 0b0000_1100");
+                    let packed_byte = (most, least)
+                        .pack()
+                        .expect("Should pack as we have provided to two known packables.");
+                    out.push(packed_byte);
+                }
+            }
+            // Start of a new byte to pack.
+            (None, b) => match b.pack(self.no_spaces) {
+                // Packable byte
+                Some(least) => {
+                    self.least = Some(least);
+                    self.fullwidth = None;
+                }
+                // Fullwidth byte
+                None => {
+                    self.least = Some(0b1111);
+                    self.fullwidth = Some(*b);
+                }
+            },
+            // fullwidth + \n
+            (Some(0b1111), b'\n') => {
+                let most = b'\n'
+                    .pack(self.no_spaces)
+                    .expect(r"Expected \n to return 0b0000_1100");
+                let packed_byte = (most, FULLWIDTH_BYTE)
+                    .pack()
+                    .expect("Should pack as we have provided to two packed chars.");
+                out.push(packed_byte);
+                out.push(self.fullwidth.unwrap());
+                self.least = None;
+                self.fullwidth = None;
+            }
+            // Full width + some other b byte that is not a \n
+            (Some(0b1111), b) => match forward_lookup(b, self.no_spaces) {
+                // Packable byte
+                Some(most) => {
+                    let packed_byte = (most, FULLWIDTH_BYTE)
+                        .pack()
+                        .expect("Should pack as we have provided to two packed chars.");
+                    out.push(packed_byte);
+                    out.push(self.fullwidth.unwrap());
+                    self.least = None;
+                    self.fullwidth = None;
+                }
+                // Fullwidth byte
+                None => {
+                    // Equivalent to a SIGNAL BYTE but keeping the function for
+                    // readability.
+                    let packed_byte = (FULLWIDTH_BYTE, FULLWIDTH_BYTE)
+                        .pack()
+                        .expect("Should pack as we have provided to two packed chars.");
+                    out.push(packed_byte);
+                    out.push(self.fullwidth.unwrap());
+                    out.push(*b);
+                    self.least = None;
+                    self.fullwidth = None;
+                }
+            },
+            // Some packable least byte with a \n most.
+            (Some(least), b'\n') => {
+                let most = b.pack(self.no_spaces).expect("Should be packable.");
+                let packed_bytes = (most, least).pack().expect("Should be packable.");
+                out.push(packed_bytes);
+                self.least = None;
+                self.fullwidth = None;
+            }
+            // least is packable + whatever b is but not a \n
+            (Some(least), b) => match b.pack(self.no_spaces) {
+                // Packable byte
+                Some(most) => {
+                    let packed_byte = (most, least).pack().expect("Should be packable.");
+                    out.push(packed_byte);
+                    self.least = None;
+                    self.fullwidth = None;
+                }
+                // Fullwidth byte
+                None => {
+                    let packed_byte = (FULLWIDTH_BYTE, least).pack().expect("Should be packable.");
+                    out.push(packed_byte);
+                    out.push(*b);
+                    self.least = None;
+                    self.fullwidth = None;
+                }
+            },
+        }
+
+        self.line_empty = *b == LINEFEED_BYTE || (self.line_empty && out.is_empty());
+        Ok(out)
+    }
+
+    /// The character held waiting for a partner.
+    fn held_char(&self) -> Option<u8> {
+        let least = self.least?;
+        match self.fullwidth {
+            Some(c) => Some(c),
+            None => {
+                Some(reverse_lookup(&least, self.no_spaces).expect("Should be a packed character."))
+            }
+        }
+    }
+
+    /// The most bytes a single input byte can emit, and the most
+    /// emitted when the \n of a line is packed.
+    pub(crate) fn emitted_max(&self) -> (usize, usize) {
+        match self.reserved {
+            ReservedBytePolicy::DisablePacking => (7, 4),
+            _ => (3, 2),
+        }
+    }
+
+    /// Whether the packer is packing or passing plain text through.
+    pub(crate) fn packing(&self) -> bool {
+        self.packing
+    }
+
+    /// Whether the packer uses the no spaces lookup.
+    pub(crate) fn no_spaces(&self) -> bool {
+        self.no_spaces
+    }
+
+    /// Whether part of a line has been packed.
+    pub(crate) fn mid_line(&self) -> bool {
+        self.least.is_some() || self.pending_cr || !self.line_empty
+    }
+
+    /// Checks that no part of a line has been packed so the mode
+    /// can be switched.
+    fn between_lines(&self) -> Result<(), MeatPackError> {
+        if self.mid_line() {
+            return Err(MeatPackError::MidLine);
+        }
+        Ok(())
+    }
+
+    /// Switches to packing from the next line, returning the
+    /// command bytes to send ahead of it.
+    pub fn enable_packing(&mut self) -> Result<[u8; 3], MeatPackError> {
+        self.between_lines()?;
+        self.packing = true;
+        Ok(MeatPackCommand::PackingEnabled.to_bytes())
+    }
+
+    /// Switches to sending plain text from the next line, returning
+    /// the command bytes to send ahead of it.
+    pub fn disable_packing(&mut self) -> Result<[u8; 3], MeatPackError> {
+        self.between_lines()?;
+        self.packing = false;
+        Ok(MeatPackCommand::PackingDisabled.to_bytes())
+    }
+
+    /// Switches to the no spaces lookup from the next line, returning
+    /// the command bytes to send ahead of it. `E` then packs in place
+    /// of the space, which is sent fullwidth unless whitespace is
+    /// being stripped.
+    pub fn enable_no_spaces(&mut self) -> Result<[u8; 3], MeatPackError> {
+        self.between_lines()?;
+        self.no_spaces = true;
+        Ok(MeatPackCommand::NoSpacesEnabled.to_bytes())
+    }
+
+    /// Switches back to the standard lookup from the next line,
+    /// returning the command bytes to send ahead of it.
+    pub fn disable_no_spaces(&mut self) -> Result<[u8; 3], MeatPackError> {
+        self.between_lines()?;
+        self.no_spaces = false;
+        Ok(MeatPackCommand::NoSpacesDisabled.to_bytes())
+    }
+
+    /// Flushes a character held waiting for a partner (and any
+    /// held `\r`) at the end of the gcode. A character can't be packed on its own so it
+    /// is sent as plain text between a `PackingDisabled` and a
+    /// `PackingEnabled` command. The packer is then ready to start
+    /// on a new line.
+    pub fn finish(&mut self) -> Result<Emitted<7>, MeatPackError> {
+        let mut out = Emitted::new();
+        if self.pending_cr {
+            self.pending_cr = false;
+            for c in self.pack_byte(&CARRIAGE_RETURN_BYTE)?.iter() {
+                out.push(*c);
+            }
+        }
+        if self.escaped {
+            // A trailing 0xFF would run into the command.
+            if self.signal_sent {
+                return Err(MeatPackError::ReservedByte(SIGNAL_BYTE));
+            }
+            for b in MeatPackCommand::PackingEnabled.to_bytes() {
+                out.push(b);
+            }
+        } else if let Some(c) = self.held_char() {
+            for b in MeatPackCommand::PackingDisabled.to_bytes() {
+                out.push(b);
+            }
+            out.push(c);
+            for b in MeatPackCommand::PackingEnabled.to_bytes() {
+                out.push(b);
+            }
+        }
+        self.escaped = false;
+        self.signal_sent = false;
+        self.least = None;
+        self.fullwidth = None;
+        self.comment_flag = false;
+        self.line_empty = true;
+        Ok(out)
+    }
+
+    /// Forgets the line in progress so the next byte starts a new
+    /// line. Used when the line has been dropped before any of it
+    /// was sent.
+    pub(crate) fn discard_line(&mut self) {
+        self.escaped = false;
+        self.signal_sent = false;
+        self.pending_cr = false;
+        self.least = None;
+        self.fullwidth = None;
+        self.comment_flag = false;
+        self.line_empty = true;
+    }
+
+    /// Drops a character held waiting for a partner, so a line cut
+    /// short can be ended cleanly by its `\n`. A line already sent
+    /// as plain text stays that way until then.
+    pub(crate) fn truncate_line(&mut self) {
+        self.pending_cr = false;
+        self.least = None;
+        self.fullwidth = None;
+    }
+
+    /// The number of characters held waiting for a partner.
+    pub(crate) fn pending(&self) -> usize {
+        usize::from(self.least.is_some()) + usize::from(self.pending_cr)
+    }
+}
@@ -0,0 +1,394 @@
+use crate::components::config::MeatPackConfig;
+use crate::components::detect::Detection;
+use crate::components::meat::{
+    Emitted, LINEFEED_BYTE, MEATPACK_HEADER, MeatPackCommand, MeatPackError, Pack, SIGNAL_BYTE,
+    Strictness, determine_command, forward_lookup, is_signal_byte,
+};
+
+#[cfg(feature = "alloc")]
+use crate::components::meat::UNPACK_TABLE;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+/// A list of state the unpackers can exist in.
+#[derive(Debug)]
+pub enum UnpackerState {
+    FirstCommandByte,
+    SecondCommandByte,
+    RightFullWidthByte,
+    LeftFullWidthByte,
+    Enabled,
+    Disabled,
+}
+
+/// What the `StreamUnpacker` produced for a byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unpacked {
+    /// Decoded characters, possibly none.
+    Chars(Emitted<2>),
+    /// A command sequence was received and applied.
+    Command(MeatPackCommand),
+}
+
+impl Unpacked {
+    /// The decoded characters, empty for a command.
+    pub fn chars(&self) -> &[u8] {
+        match self {
+            Unpacked::Chars(chars) => chars.as_slice(),
+            Unpacked::Command(_) => &[],
+        }
+    }
+}
+
+/// An unpacker that holds no line buffer. Each incoming byte yields
+/// zero, one or two decoded characters in the order they appear in
+/// the gcode, in the same way as the firmware `handle_rx_char` and
+/// `get_result_char` pair. This lets it sit directly in front of an
+/// existing character based command parser. Command sequences
+/// are applied and reported so the caller can act on them.
+pub struct StreamUnpacker {
+    state: UnpackerState,
+    packing: bool,
+    no_spaces: bool,
+    lossless: bool,
+    strictness: Strictness,
+    received: usize,
+    held: u8,
+    line_empty: bool,
+}
+
+impl Default for StreamUnpacker {
+    /// The default implementation of the stream unpacker.
+    fn default() -> Self {
+        Self {
+            state: UnpackerState::Disabled,
+            packing: false,
+            no_spaces: false,
+            lossless: false,
+            strictness: Strictness::default(),
+            received: 0,
+            held: 0,
+            line_empty: true,
+        }
+    }
+}
+
+impl StreamUnpacker {
+    /// Keeps empty lines, as sent by a lossless packer, rather
+    /// than dropping them.
+    pub fn with_lossless(
+        mut self,
+        lossless: bool,
+    ) -> Self {
+        self.lossless = lossless;
+        self
+    }
+
+    /// Starts in the mode a `detect` found the data to be in
+    /// rather than waiting for a header. Headerless data is
+    /// unpacked from the first byte, with or without spaces as
+    /// detected, and, as there is no header to wait for,
+    /// strictness no longer requires one.
+    pub fn with_detection(
+        mut self,
+        detection: Detection,
+    ) -> Self {
+        if !matches!(detection, Detection::Header { .. }) {
+            if let Detection::Headerless { no_spaces } = detection {
+                self.no_spaces = no_spaces;
+            }
+            self.packing = detection.is_packed();
+            self.state = self.mode();
+            self.received = MEATPACK_HEADER.len();
+        }
+        self
+    }
+
+    /// Sets how closely the data is checked against the protocol.
+    pub fn with_strictness(
+        mut self,
+        strictness: Strictness,
+    ) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// Unpacks a single meatpacked byte returning the characters
+    /// that are now ready. A packed byte whose first character is
+    /// fullwidth yields nothing until the fullwidth byte arrives,
+    /// when both characters are returned together.
+    pub fn unpack(
+        &mut self,
+        byte: &u8,
+    ) -> Result<Unpacked, MeatPackError> {
+        let mut out = Emitted::new();
+
+        // Strictly the data starts with the header. A missing
+        // header is only reported once and the rest unpacked as is.
+        if self.strictness == Strictness::Strict && self.received < MEATPACK_HEADER.len() {
+            if *byte != MEATPACK_HEADER[self.received] {
+                self.received = MEATPACK_HEADER.len();
+                return Err(MeatPackError::MissingHeader);
+            }
+            self.received += 1;
+        }
+
+        // First check if it is a signal byte
+        // and handle the scenarios. A third signal byte
+        // is the `SignalByte` command and is handled below.
+        if is_signal_byte(byte) && !matches!(self.state, UnpackerState::SecondCommandByte) {
+            match self.state {
+                UnpackerState::FirstCommandByte => {
+                    self.state = UnpackerState::SecondCommandByte;
+                    return Ok(Unpacked::Chars(out));
+                }
+                UnpackerState::Disabled => {
+                    self.state = UnpackerState::FirstCommandByte;
+                    return Ok(Unpacked::Chars(out));
+                }
+                UnpackerState::Enabled => {
+                    self.state = UnpackerState::FirstCommandByte;
+                    return Ok(Unpacked::Chars(out));
+                }
+                _ => {
+                    return Err(MeatPackError::InvalidState);
+                }
+            }
+        }
+
+        // Handle non signal scenarios.
+        match self.state {
+            UnpackerState::Disabled => {
+                if self.strictness == Strictness::Strict && *byte == 0 {
+                    return Err(MeatPackError::NulByte);
+                }
+                out.push(*byte);
+            }
+            UnpackerState::Enabled => {
+                let (most, least) = byte.unpack(self.no_spaces);
+
+                // most, least
+                // Check if we need to wait for a
+                // fullwidth byte.
+                match (most, least) {
+                    // \n\n packed byte. Just return one \n
+                    // unless it is an empty line.
+                    (10, 10) => {
+                        if !self.line_empty || self.lossless {
+                            out.push(10);
+                        }
+                    }
+                    // most is a full width byte
+                    (0, 1..) => {
+                        out.push(least);
+                        self.state = UnpackerState::RightFullWidthByte;
+                    }
+                    // least is a full width byte so hold
+                    // most back until it arrives.
+                    (1.., 0) => {
+                        self.held = most;
+                        self.state = UnpackerState::LeftFullWidthByte;
+                    }
+                    // Should be dealt with by the command bytes section.
+                    (0, 0) => {
+                        unreachable!();
+                    }
+                    // Two unpacked packable bytes.
+                    (most, least) => {
+                        out.push(least);
+                        out.push(most);
+                    }
+                }
+            }
+            UnpackerState::SecondCommandByte => {
+                let cmd = determine_command(byte)?;
+                self.handle_command(cmd);
+                return Ok(Unpacked::Command(cmd));
+            }
+            UnpackerState::FirstCommandByte => {
+                if self.strictness == Strictness::Strict && !self.packing {
+                    self.state = self.mode();
+                    return Err(MeatPackError::StraySignalByte);
+                }
+                // A lone signal byte in plain text is itself.
+                if !self.packing {
+                    self.state = UnpackerState::Disabled;
+                    out.push(SIGNAL_BYTE);
+                } else {
+                    self.state = UnpackerState::RightFullWidthByte;
+                    self.check_fullwidth(byte)?;
+                }
+                out.push(*byte);
+            }
+            UnpackerState::RightFullWidthByte => {
+                self.state = self.mode();
+                self.check_fullwidth(byte)?;
+                out.push(*byte);
+            }
+            UnpackerState::LeftFullWidthByte => {
+                self.state = self.mode();
+                self.check_fullwidth(byte)?;
+                out.push(*byte);
+                out.push(self.held);
+            }
+        }
+
+        if let Some(last) = out.last() {
+            self.line_empty = *last == LINEFEED_BYTE;
+        }
+        Ok(Unpacked::Chars(out))
+    }
+
+    /// Unpacks the run of bytes at the start of `in_buf` that need
+    /// none of the state machine, returning how many were used:
+    /// packed bytes without a fullwidth marker or, when not strict,
+    /// plain text up to the next signal byte.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn unpack_run(
+        &mut self,
+        in_buf: &[u8],
+        out_buf: &mut Vec<u8>,
+    ) -> usize {
+        match self.state {
+            UnpackerState::Enabled => {
+                let table = &UNPACK_TABLE[usize::from(self.no_spaces)];
+                let run = in_buf
+                    .iter()
+                    .position(|b| b & 0b1111 == 0b1111 || b >> 4 == 0b1111)
+                    .unwrap_or(in_buf.len());
+                for byte in &in_buf[..run] {
+                    match table[usize::from(*byte)] {
+                        // \n\n packed byte. Just return one \n
+                        // unless it is an empty line.
+                        (10, 10) => {
+                            if !self.line_empty || self.lossless {
+                                out_buf.push(LINEFEED_BYTE);
+                                self.line_empty = true;
+                            }
+                        }
+                        (most, least) => {
+                            out_buf.push(least);
+                            out_buf.push(most);
+                            self.line_empty = most == LINEFEED_BYTE;
+                        }
+                    }
+                }
+                run
+            }
+            UnpackerState::Disabled if self.strictness == Strictness::Permissive => {
+                let run = in_buf
+                    .iter()
+                    .position(is_signal_byte)
+                    .unwrap_or(in_buf.len());
+                if let Some(last) = in_buf[..run].last() {
+                    out_buf.extend(&in_buf[..run]);
+                    self.line_empty = *last == LINEFEED_BYTE;
+                }
+                run
+            }
+            _ => 0,
+        }
+    }
+
+    /// Strictly a fullwidth byte is only sent for a character
+    /// that can't be packed.
+    fn check_fullwidth(
+        &self,
+        byte: &u8,
+    ) -> Result<(), MeatPackError> {
+        if self.strictness == Strictness::Permissive {
+            return Ok(());
+        }
+        if *byte == 0 {
+            return Err(MeatPackError::NulByte);
+        }
+        if forward_lookup(byte, self.no_spaces).is_some() {
+            return Err(MeatPackError::UnneededFullwidth(*byte));
+        }
+        Ok(())
+    }
+
+    /// The state to return to once a command or
+    /// fullwidth byte has been dealt with.
+    fn mode(&self) -> UnpackerState {
+        if self.packing {
+            UnpackerState::Enabled
+        } else {
+            UnpackerState::Disabled
+        }
+    }
+
+    /// Handles the command byte combinations that
+    /// exist in the meatpack spec. Commands that do not
+    /// change the mode return to the mode that was active.
+    fn handle_command(
+        &mut self,
+        cmd: MeatPackCommand,
+    ) {
+        match cmd {
+            MeatPackCommand::PackingEnabled => {
+                self.packing = true;
+            }
+            MeatPackCommand::PackingDisabled => {
+                self.packing = false;
+            }
+            MeatPackCommand::ResetAll => {
+                self.packing = false;
+                self.no_spaces = false;
+            }
+            MeatPackCommand::QueryConfig => {}
+            MeatPackCommand::NoSpacesEnabled => {
+                self.no_spaces = true;
+            }
+            MeatPackCommand::NoSpacesDisabled => {
+                self.no_spaces = false;
+            }
+            MeatPackCommand::SignalByte => {}
+        }
+        self.state = self.mode();
+    }
+
+    /// The current configuration, as reported in reply to a
+    /// `QueryConfig` with `MeatPackConfig::report`.
+    pub fn config(&self) -> MeatPackConfig {
+        MeatPackConfig::new(self.packing, self.no_spaces)
+    }
+
+    /// Ends the stream so a new one can be started. Errors if it
+    /// ends part way through a character or command sequence as
+    /// the rest of it will never arrive, or strictly part way
+    /// through a line.
+    pub fn finish(&mut self) -> Result<(), MeatPackError> {
+        let mid_line = !self.line_empty;
+        self.line_empty = true;
+        match self.state {
+            UnpackerState::Enabled | UnpackerState::Disabled => {}
+            _ => {
+                self.state = self.mode();
+                return Err(MeatPackError::InvalidState);
+            }
+        }
+        if self.strictness == Strictness::Strict && mid_line {
+            return Err(MeatPackError::TrailingPartialLine);
+        }
+        Ok(())
+    }
+
+    /// Whether part of a line has been unpacked.
+    pub(crate) fn mid_line(&self) -> bool {
+        !self.line_empty
+    }
+
+    /// Abandons whatever character, command sequence or line
+    /// was in progress and returns to the current mode.
+    pub(crate) fn resync(&mut self) {
+        self.state = self.mode();
+        self.line_empty = true;
+    }
+
+    /// Forgets the characters emitted so far on the current line
+    /// so a following empty line is treated as such.
+    pub(crate) fn discard_line(&mut self) {
+        self.line_empty = true;
+    }
+}
@@ -1,7 +1,57 @@
 use core::str::from_utf8;
 use std::{string::String, vec::Vec};
 
-use crate::{MEATPACK_HEADER, MeatPackResult, Packer, Unpacker};
+use crate::{
+    BufferedPacker, BufferedUnpacker, LineBuffer, MEATPACK_HEADER, MeatPackError, MeatPackResult,
+    Packer, Unpacker,
+};
+
+/// Packs `gcode` a byte at a time behind the header the packer
+/// expects, finishing an unterminated final line.
+fn pack_all<B: LineBuffer>(
+    mut packer: BufferedPacker<B>,
+    gcode: &[u8],
+) -> Result<Vec<u8>, MeatPackError> {
+    let (header, len) = packer.header();
+    let mut meat = header[..len].to_vec();
+    for b in gcode.iter() {
+        if let MeatPackResult::Line(line) | MeatPackResult::Partial(line) = packer.pack(b)? {
+            meat.extend(line);
+        }
+    }
+    if let Some(line) = packer.finish(false)? {
+        meat.extend(line);
+    }
+    Ok(meat)
+}
+
+/// Unpacks `meat` a byte at a time, finishing an unterminated
+/// final line.
+fn unpack_all<B: LineBuffer>(
+    mut unpacker: BufferedUnpacker<B>,
+    meat: &[u8],
+) -> Result<Vec<u8>, MeatPackError> {
+    let mut gcode: Vec<u8> = Vec::new();
+    for b in meat.iter() {
+        if let MeatPackResult::Line(line) | MeatPackResult::Partial(line) = unpacker.unpack(b)? {
+            gcode.extend(line);
+        }
+    }
+    if let Some(line) = unpacker.finish(false)? {
+        gcode.extend(line);
+    }
+    Ok(gcode)
+}
+
+/// The lines of some unpacked gcode, each with its `\n`.
+fn lines_of(gcode: &[u8]) -> Vec<&str> {
+    from_utf8(gcode).unwrap().split_inclusive('\n').collect()
+}
+
+/// The header followed by `bytes`.
+fn with_header(bytes: &[u8]) -> Vec<u8> {
+    [MEATPACK_HEADER.as_slice(), bytes].concat()
+}
 
 #[test]
 fn test_pack_unpack_strip_comments_false() {
@@ -28,6 +78,9 @@ M204 P4000 R1200 T4000
                 out.extend(line);
             }
             Ok(MeatPackResult::WaitingForNextByte) => {}
+            Ok(MeatPackResult::Command(_)) => {}
+            Ok(MeatPackResult::Resynced(_)) => {}
+            Ok(MeatPackResult::Partial(_)) => {}
             Err(_) => panic!("Should not enter here"),
         }
     }
@@ -43,6 +96,9 @@ M204 P4000 R1200 T4000
         let res = unpacker.unpack(byte);
         match res {
             Ok(MeatPackResult::WaitingForNextByte) => {}
+            Ok(MeatPackResult::Command(_)) => {}
+            Ok(MeatPackResult::Resynced(_)) => {}
+            Ok(MeatPackResult::Partial(_)) => {}
             Ok(MeatPackResult::Line(line)) => {
                 let s = from_utf8(line).unwrap();
                 unpacked.push_str(s);
@@ -79,6 +135,9 @@ M204 P4000 R1200 T4000
                 out.extend(line);
             }
             Ok(MeatPackResult::WaitingForNextByte) => {}
+            Ok(MeatPackResult::Command(_)) => {}
+            Ok(MeatPackResult::Resynced(_)) => {}
+            Ok(MeatPackResult::Partial(_)) => {}
             Err(_) => panic!("Should not enter here"),
         }
     }
@@ -94,6 +153,9 @@ M204 P4000 R1200 T4000
         let res = unpacker.unpack(byte);
         match res {
             Ok(MeatPackResult::WaitingForNextByte) => {}
+            Ok(MeatPackResult::Command(_)) => {}
+            Ok(MeatPackResult::Resynced(_)) => {}
+            Ok(MeatPackResult::Partial(_)) => {}
             Ok(MeatPackResult::Line(line)) => {
                 let s = from_utf8(line).unwrap();
                 unpacked.push_str(s);
@@ -181,3 +243,1179 @@ M204P4000R1200T4000
     let unpacked = String::from_utf8(unpacked).unwrap();
     assert_eq!(expected, unpacked)
 }
+
+#[cfg(feature = "std")]
+#[test]
+fn test_std_io_round_trip() {
+    use std::{
+        env, fs,
+        io::{self, Read, Write},
+    };
+
+    use crate::{MeatPackReader, MeatPackWriter, VecPacker, VecUnpacker};
+
+    let mut path = env::current_dir().unwrap();
+    path.push("test_files");
+    path.push("box.gcode");
+    let gcode = fs::read(path).unwrap();
+
+    // The writer should produce the same bytes as pack_slice.
+    let mut expected: Vec<u8> = Vec::new();
+    Packer::<128>::pack_slice(&gcode, &mut expected, false, true).unwrap();
+
+    let mut writer = MeatPackWriter::new(Vec::new(), Packer::<128>::new(false, true));
+    io::copy(&mut gcode.as_slice(), &mut writer).unwrap();
+    let meat = writer.finish().unwrap();
+    assert_eq!(expected, meat);
+
+    // As does a packer with a runtime sized buffer.
+    let mut writer = MeatPackWriter::new(Vec::new(), VecPacker::with_capacity(4, false, true));
+    io::copy(&mut gcode.as_slice(), &mut writer).unwrap();
+    assert_eq!(expected, writer.finish().unwrap());
+
+    let mut unpacked: Vec<u8> = Vec::new();
+    Unpacker::<128>::unpack_slice(&meat, &mut unpacked).unwrap();
+
+    // Read back in awkwardly sized reads to exercise partial lines.
+    let mut reader = MeatPackReader::new(meat.as_slice(), Unpacker::<128>::default());
+    let mut out: Vec<u8> = Vec::new();
+    let mut buf = [0u8; 7];
+    loop {
+        let n = reader.read(&mut buf).unwrap();
+        if n == 0 {
+            break;
+        }
+        out.write_all(&buf[..n]).unwrap();
+    }
+    assert_eq!(unpacked, out);
+
+    let mut reader = MeatPackReader::new(meat.as_slice(), VecUnpacker::default());
+    let mut out: Vec<u8> = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(unpacked, out);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_std_io_unterminated_line() {
+    use std::io::{self, Read, Write};
+
+    use crate::{MeatPackReader, MeatPackWriter};
+
+    // The final line is finished as pack_slice does.
+    let gcode = b"G1 X1\nG1 X2";
+    let mut expected: Vec<u8> = Vec::new();
+    Packer::<64>::pack_slice(gcode, &mut expected, true, false).unwrap();
+    let mut writer = MeatPackWriter::new(Vec::new(), Packer::<64>::default());
+    writer.write_all(gcode).unwrap();
+    let meat = writer.finish().unwrap();
+    assert_eq!(expected, meat);
+
+    let mut reader = MeatPackReader::new(meat.as_slice(), Unpacker::<64>::default());
+    let mut out: Vec<u8> = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, gcode);
+
+    // Ending part way through a character is still an error.
+    let meat = with_header(&[0b0001_1101, 0b1100_1100, 0b1111_1101]);
+    let mut reader = MeatPackReader::new(meat.as_slice(), Unpacker::<64>::default());
+    let mut out: Vec<u8> = Vec::new();
+    let err = reader.read_to_end(&mut out).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    assert_eq!(out, b"G1\n");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_std_io_writer_errors_and_drop() {
+    use std::io::{self, Write};
+
+    use crate::MeatPackWriter;
+
+    let mut expected: Vec<u8> = Vec::new();
+    Packer::<64>::pack_slice(b"G1 X1\n", &mut expected, false, false).unwrap();
+
+    // The bytes before a bad one are reported as written and the
+    // error comes from the next call.
+    let mut writer = MeatPackWriter::new(Vec::new(), Packer::<64>::default());
+    assert_eq!(writer.write(b"G1 X1\n\xffG2").unwrap(), 6);
+    let err = writer.write(b"\xffG2").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(writer.get_ref(), &expected);
+
+    // A bad first byte errors straight away.
+    let mut writer = MeatPackWriter::new(Vec::new(), Packer::<64>::default());
+    assert!(writer.write(b"\xffG2").is_err());
+
+    // Dropping the writer still writes an unterminated final line.
+    let mut expected: Vec<u8> = Vec::new();
+    Packer::<64>::pack_slice(b"G1 X1\nG1 X2", &mut expected, true, false).unwrap();
+    let mut meat: Vec<u8> = Vec::new();
+    let mut writer = MeatPackWriter::new(&mut meat, Packer::<64>::default());
+    writer.write_all(b"G1 X1\nG1 X2").unwrap();
+    drop(writer);
+    assert_eq!(meat, expected);
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_pack_unpack_into_small_buffers() {
+    use std::{env, fs};
+
+    let mut path = env::current_dir().unwrap();
+    path.push("test_files");
+    path.push("box.gcode");
+    let gcode = fs::read(path).unwrap();
+
+    let mut expected: Vec<u8> = Vec::new();
+    Packer::<128>::pack_slice(&gcode, &mut expected, false, true).unwrap();
+
+    // Pack into a buffer far smaller than a line, resuming each
+    // time it fills up.
+    let mut packer = Packer::<128>::new(false, true);
+    let mut meat: Vec<u8> = Vec::new();
+    let mut out = [0u8; 5];
+    let mut rest = gcode.as_slice();
+    loop {
+        let progress = packer.pack_into(rest, &mut out).unwrap();
+        meat.extend(&out[..progress.written]);
+        rest = &rest[progress.consumed..];
+        if rest.is_empty() && progress.written < out.len() {
+            break;
+        }
+    }
+    assert_eq!(expected, meat);
+
+    let mut expected: Vec<u8> = Vec::new();
+    Unpacker::<128>::unpack_slice(&meat, &mut expected).unwrap();
+
+    let mut unpacker = Unpacker::<128>::default();
+    let mut unpacked: Vec<u8> = Vec::new();
+    let mut out = [0u8; 3];
+    let mut rest = meat.as_slice();
+    loop {
+        let progress = unpacker.unpack_into(rest, &mut out).unwrap();
+        unpacked.extend(&out[..progress.written]);
+        rest = &rest[progress.consumed..];
+        if rest.is_empty() && progress.written < out.len() {
+            break;
+        }
+    }
+    assert_eq!(expected, unpacked);
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_pack_unpack_into_finish() {
+    use crate::PositionedError;
+
+    let gcode = b"G1 X12345\nG2 Y";
+    let mut expected: Vec<u8> = Vec::new();
+    Packer::<64>::pack_slice(gcode, &mut expected, true, false).unwrap();
+
+    // A completed line held by pack_into is not lost to pack or
+    // finish, which refuse until it has been written out.
+    let mut packer = Packer::<64>::default();
+    let mut out = [0u8; 4];
+    let progress = packer.pack_into(gcode, &mut out).unwrap();
+    let mut meat = out[..progress.written].to_vec();
+    let err = packer.finish(false).unwrap_err();
+    assert!(matches!(err.error, MeatPackError::UndrainedLine(_)));
+    assert!(matches!(
+        packer.pack(&b'G'),
+        Err(PositionedError {
+            error: MeatPackError::UndrainedLine(_),
+            ..
+        })
+    ));
+
+    let mut rest = &gcode[progress.consumed..];
+    while !rest.is_empty() {
+        let progress = packer.pack_into(rest, &mut out).unwrap();
+        meat.extend(&out[..progress.written]);
+        rest = &rest[progress.consumed..];
+    }
+    // The unterminated final line comes out through finish_into.
+    loop {
+        let written = packer.finish_into(&mut out).unwrap();
+        if written == 0 {
+            break;
+        }
+        meat.extend(&out[..written]);
+    }
+    assert_eq!(meat, expected);
+
+    let mut unpacker = Unpacker::<64>::default();
+    let progress = unpacker.unpack_into(&meat, &mut out).unwrap();
+    let mut unpacked = out[..progress.written].to_vec();
+    let err = unpacker.finish(false).unwrap_err();
+    assert!(matches!(err.error, MeatPackError::UndrainedLine(_)));
+    assert!(matches!(
+        unpacker.unpack(&meat[progress.consumed]),
+        Err(PositionedError {
+            error: MeatPackError::UndrainedLine(_),
+            ..
+        })
+    ));
+
+    let mut rest = &meat[progress.consumed..];
+    while !rest.is_empty() {
+        let progress = unpacker.unpack_into(rest, &mut out).unwrap();
+        unpacked.extend(&out[..progress.written]);
+        rest = &rest[progress.consumed..];
+    }
+    loop {
+        let written = unpacker.finish_into(&mut out).unwrap();
+        if written == 0 {
+            break;
+        }
+        unpacked.extend(&out[..written]);
+    }
+    assert_eq!(unpacked, gcode);
+}
+
+#[test]
+fn test_pack_into_header_is_sent_once() {
+    let mut packer = Packer::<32>::new(false, false);
+    let mut out = [0u8; 32];
+    let progress = packer.pack_into(b"G1\n", &mut out).unwrap();
+    assert_eq!(out[..3], MEATPACK_HEADER);
+    assert!(progress.written > 3);
+
+    // The command bytes are sent by the caller, not again as a header.
+    let cmd = packer.enable_no_spaces().unwrap();
+    let progress = packer.pack_into(b"G1\n", &mut out).unwrap();
+    assert!(!out[..progress.written].contains(&cmd[0]));
+    let cmd = packer.disable_no_spaces().unwrap();
+    let progress = packer.pack_into(b"G1\n", &mut out).unwrap();
+    assert!(!out[..progress.written].contains(&cmd[0]));
+
+    // A packer that starts with no spaces sends both commands.
+    let mut packer = Packer::<32>::new(false, true);
+    let progress = packer.pack_into(b"G1\n", &mut out).unwrap();
+    assert_eq!(out[..6], [255, 255, 251, 255, 255, 247]);
+    assert!(progress.written > 6);
+}
+
+#[test]
+fn test_iter_round_trip() {
+    use std::{env, fs};
+
+    use crate::MeatPackIterExt;
+
+    let mut path = env::current_dir().unwrap();
+    path.push("test_files");
+    path.push("box.gcode");
+    let gcode = fs::read(path).unwrap();
+
+    let unpacked: Result<Vec<u8>, _> = gcode
+        .iter()
+        .copied()
+        .meatpack_with(Packer::<128>::new(false, false))
+        .map(Result::unwrap)
+        .meatunpack()
+        .collect();
+    assert_eq!(gcode, unpacked.unwrap());
+
+    // An unterminated final line is finished at the end.
+    let unpacked: Result<Vec<u8>, _> = b"G1 X1\nG1"
+        .iter()
+        .copied()
+        .meatpack_with(Packer::<128>::new(false, false))
+        .map(Result::unwrap)
+        .meatunpack()
+        .collect();
+    assert_eq!(unpacked.unwrap(), b"G1 X1\nG1");
+
+    // Packed data that ends part way through a character errors.
+    let unpacked: Vec<_> = MEATPACK_HEADER
+        .iter()
+        .copied()
+        .chain([0b1111_1101])
+        .meatunpack()
+        .collect();
+    assert!(matches!(
+        unpacked.as_slice(),
+        [Err(MeatPackError::InvalidState)]
+    ));
+}
+
+#[test]
+fn test_stream_packer_emits_bytes_as_soon_as_possible() {
+    use crate::StreamPacker;
+
+    // Each packed byte is emitted once its partner arrives.
+    let mut stream = StreamPacker::new(false, false);
+    let lens: Vec<usize> = b"G1 X1\n"
+        .iter()
+        .map(|b| stream.pack(b).unwrap().len())
+        .collect();
+    assert_eq!(lens, [0, 1, 0, 1, 0, 1]);
+
+    // The README example packs 27 bytes into 16.
+    let gcode = b"G1 X113.214 Y91.45 E1.3154\n";
+    let mut stream = StreamPacker::new(false, false);
+    let mut out: Vec<u8> = Vec::new();
+    out.extend(&MEATPACK_HEADER);
+    for b in gcode.iter() {
+        out.extend(stream.pack(b).unwrap().iter());
+    }
+    assert_eq!(out.len() - MEATPACK_HEADER.len(), 16);
+    assert_eq!(unpack_all(Unpacker::<64>::default(), &out).unwrap(), gcode);
+}
+
+#[test]
+fn test_stream_unpacker_yields_characters() {
+    use crate::{MeatPackCommand, StreamUnpacker, Unpacked};
+
+    let mut unpacker = StreamUnpacker::default();
+    assert!(unpacker.unpack(&255).unwrap().chars().is_empty());
+    assert!(unpacker.unpack(&255).unwrap().chars().is_empty());
+    assert_eq!(
+        unpacker.unpack(&251).unwrap(),
+        Unpacked::Command(MeatPackCommand::PackingEnabled)
+    );
+    // "M1" where M is fullwidth. The 1 is held until the M arrives.
+    assert!(unpacker.unpack(&0b0001_1111).unwrap().chars().is_empty());
+    assert_eq!(unpacker.unpack(&b'M').unwrap().chars(), b"M1");
+    // " G" followed by "1\n"
+    assert_eq!(unpacker.unpack(&0b1101_1011).unwrap().chars(), b" G");
+    assert_eq!(unpacker.unpack(&0b1100_0001).unwrap().chars(), b"1\n");
+    // "T" fullwidth followed by "0".
+    assert_eq!(unpacker.unpack(&0b1111_0000).unwrap().chars(), b"0");
+    assert_eq!(unpacker.unpack(&b'T').unwrap().chars(), b"T");
+}
+
+#[test]
+fn test_unpacker_reports_commands() {
+    use crate::MeatPackCommand;
+
+    let mut unpacker = Unpacker::<64>::default();
+    let mut commands: Vec<MeatPackCommand> = Vec::new();
+    let mut lines: Vec<Vec<u8>> = Vec::new();
+
+    // Header, a packed "G1\n", a config query, another packed
+    // line and then a switch back to plain text.
+    let mut meat = with_header(&[0b0001_1101, 0b1100_1100]);
+    meat.extend([255, 255, 248]);
+    meat.extend([0b0001_1101, 0b1100_1100]);
+    meat.extend([255, 255, 250]);
+    meat.extend(b"M1\n");
+
+    for b in meat.iter() {
+        match unpacker.unpack(b).unwrap() {
+            MeatPackResult::Command(cmd) => commands.push(cmd),
+            MeatPackResult::Line(line) => lines.push(line.to_vec()),
+            MeatPackResult::WaitingForNextByte
+            | MeatPackResult::Partial(_)
+            | MeatPackResult::Resynced(_) => {}
+        }
+    }
+
+    assert_eq!(
+        commands,
+        [
+            MeatPackCommand::PackingEnabled,
+            MeatPackCommand::QueryConfig,
+            MeatPackCommand::PackingDisabled
+        ]
+    );
+    assert_eq!(
+        lines,
+        [b"G1\n".to_vec(), b"G1\n".to_vec(), b"M1\n".to_vec()]
+    );
+}
+
+#[test]
+fn test_query_config_report_round_trip() {
+    use crate::{MeatPackCommand, MeatPackConfig};
+
+    // The firmware side replies to a query with its state.
+    let mut unpacker = Unpacker::<64>::default();
+    let meat = with_header(&[255, 255, 247, 255, 255, 248]);
+    let mut report = None;
+    for b in meat.iter() {
+        if let MeatPackResult::Command(MeatPackCommand::QueryConfig) = unpacker.unpack(b).unwrap() {
+            report = Some(unpacker.config().report());
+        }
+    }
+    let report = report.unwrap();
+    assert_eq!(report.as_slice(), b"[MP] PV01 ON NSP\n");
+
+    // And the host parses it back.
+    let config = MeatPackConfig::parse(&report).unwrap();
+    assert_eq!(config, MeatPackConfig::new(true, true));
+
+    for (packing, no_spaces) in [(false, false), (false, true), (true, false)] {
+        let config = MeatPackConfig::new(packing, no_spaces);
+        assert_eq!(MeatPackConfig::parse(&config.report()).unwrap(), config);
+    }
+
+    // Leading spaces and CRLF endings are tolerated.
+    let config = MeatPackConfig::parse(b" [MP] PV01 OFF ESP\r\n").unwrap();
+    assert_eq!(config, MeatPackConfig::new(false, false));
+
+    assert!(MeatPackConfig::parse(b"ok\n").is_err());
+    assert!(MeatPackConfig::parse(b"[MP] PV01 ON\n").is_err());
+    assert!(MeatPackConfig::parse(b"[MP] PVxx ON NSP\n").is_err());
+}
+
+#[test]
+fn test_packer_mode_switching() {
+    let gcode: [&[u8]; 4] = [
+        b"G1 X1.5 E2\n",
+        b"M117 Plain text\n",
+        b"G1 X2.5 E3\n",
+        b"G1 X3.5 E4\n",
+    ];
+
+    let mut packer = Packer::<64>::new(false, false);
+    let mut meat: Vec<u8> = Vec::new();
+    meat.extend(&MEATPACK_HEADER);
+    for (i, line) in gcode.iter().enumerate() {
+        match i {
+            1 => meat.extend(packer.disable_packing().unwrap()),
+            2 => meat.extend(packer.enable_packing().unwrap()),
+            3 => meat.extend(packer.enable_no_spaces().unwrap()),
+            _ => {}
+        }
+        for b in line.iter() {
+            if let MeatPackResult::Line(packed) = packer.pack(b).unwrap() {
+                meat.extend(packed);
+            }
+        }
+    }
+    // The plain text line is sent as is.
+    assert!(meat.windows(16).any(|w| w == gcode[1]));
+    assert_eq!(
+        unpack_all(Unpacker::<64>::default(), &meat).unwrap(),
+        gcode.concat()
+    );
+
+    // Modes can't be switched part way through a line.
+    packer.pack(&b'G').unwrap();
+    assert!(matches!(
+        packer.disable_packing(),
+        Err(MeatPackError::MidLine)
+    ));
+}
+
+#[test]
+fn test_overflow_policy() {
+    use crate::OverflowPolicy;
+
+    let gcode = b"M117 A message far too long for the buffer\nG1 X1\n";
+
+    // Erroring rather than panicking.
+    let mut packer = Packer::<8>::new(false, false);
+    let err = gcode.iter().find_map(|b| packer.pack(b).err());
+    assert!(matches!(
+        err.map(|e| e.error),
+        Some(MeatPackError::BufferFull)
+    ));
+    let mut unpacker = Unpacker::<8>::default();
+    let err = gcode.iter().find_map(|b| unpacker.unpack(b).err());
+    assert!(matches!(
+        err.map(|e| e.error),
+        Some(MeatPackError::BufferFull)
+    ));
+
+    // The line that did not fit is dropped and the next lines come out.
+    let gcode = b"M117 A message far too long\nG1 X1\nG1 X2\n";
+    let mut packer = Packer::<8>::new(false, false);
+    let mut meat: Vec<u8> = Vec::new();
+    meat.extend(&MEATPACK_HEADER);
+    let mut errors = 0;
+    for b in gcode.iter() {
+        match packer.pack(b) {
+            Ok(MeatPackResult::Line(line)) => meat.extend(line),
+            Ok(_) => {}
+            Err(e) => {
+                assert!(matches!(e.error, MeatPackError::BufferFull));
+                errors += 1;
+            }
+        }
+    }
+    assert_eq!(errors, 1);
+    let unpacked = unpack_all(Unpacker::<128>::default(), &meat).unwrap();
+    assert_eq!(unpacked, b"G1 X1\nG1 X2\n");
+
+    let meat = pack_all(Packer::<128>::new(false, false), gcode).unwrap();
+    let mut unpacker = Unpacker::<8>::default();
+    let mut lines: Vec<String> = Vec::new();
+    let mut errors = 0;
+    for b in meat.iter() {
+        match unpacker.unpack(b) {
+            Ok(MeatPackResult::Line(line)) => lines.push(String::from(from_utf8(line).unwrap())),
+            Ok(_) => {}
+            Err(e) => {
+                assert!(matches!(e.error, MeatPackError::BufferFull));
+                errors += 1;
+            }
+        }
+    }
+    assert_eq!(errors, 1);
+    assert_eq!(lines, ["G1 X1\n", "G1 X2\n"]);
+    assert_eq!(unpacker.line_number(), 4);
+
+    let gcode = b"M117 A message far too long for the buffer\nG1 X1\n";
+
+    // Partial chunks join up to the same output.
+    let mut packer = Packer::<8>::new(false, false).with_overflow(OverflowPolicy::Partial);
+    let mut meat: Vec<u8> = Vec::new();
+    meat.extend(&MEATPACK_HEADER);
+    let mut partials = 0;
+    for b in gcode.iter() {
+        match packer.pack(b).unwrap() {
+            MeatPackResult::Partial(chunk) => {
+                assert!(chunk.len() <= 8);
+                partials += 1;
+                meat.extend(chunk);
+            }
+            MeatPackResult::Line(line) => meat.extend(line),
+            _ => {}
+        }
+    }
+    assert!(partials > 0);
+    assert_eq!(
+        pack_all(Packer::<64>::new(false, false), gcode).unwrap(),
+        meat
+    );
+
+    let unpacker = Unpacker::<4>::default().with_overflow(OverflowPolicy::Partial);
+    assert_eq!(unpack_all(unpacker, &meat).unwrap(), gcode);
+
+    // Truncated lines keep their \n and the next line is intact.
+    let unpacker = Unpacker::<8>::default().with_overflow(OverflowPolicy::Truncate);
+    let unpacked = unpack_all(unpacker, gcode).unwrap();
+    assert_eq!(lines_of(&unpacked), ["M117 A \n", "G1 X1\n"]);
+
+    let packer = Packer::<8>::new(false, false).with_overflow(OverflowPolicy::Truncate);
+    let meat = pack_all(packer, gcode).unwrap();
+    let unpacked = unpack_all(Unpacker::<64>::default(), &meat).unwrap();
+    let unpacked = lines_of(&unpacked);
+    assert!(unpacked[0].starts_with("M117"));
+    assert_eq!(unpacked[1..], ["G1 X1\n"]);
+}
+
+#[test]
+fn test_pack_truncate_ends_lines_cleanly() {
+    use crate::{OverflowPolicy, ReservedBytePolicy};
+
+    let cases: [(&[u8], ReservedBytePolicy); 2] = [
+        (b"G1 X123456789\nG2\n", ReservedBytePolicy::Error),
+        (
+            b"G1G1G1 X1\xffABCDEFGH\nG2\n",
+            ReservedBytePolicy::DisablePacking,
+        ),
+    ];
+    for (gcode, reserved) in cases {
+        let end = gcode.iter().position(|b| *b == b'\n').unwrap();
+        let (first, rest) = (&gcode[..end], &gcode[end + 1..]);
+        // Every size cuts the first line at a different point, both
+        // with a character held for a partner and with the line
+        // switched to plain text before or after the cut.
+        for size in 6..24 {
+            let mut buffer = [0u8; 24];
+            let packer = BufferedPacker::with_buffer(&mut buffer[..size], false, false)
+                .with_overflow(OverflowPolicy::Truncate)
+                .with_reserved_bytes(reserved);
+            let meat = pack_all(packer, gcode).unwrap();
+            let unpacked = unpack_all(Unpacker::<64>::default(), &meat).unwrap();
+            let (kept, rest_out) = unpacked.split_at(unpacked.len() - rest.len());
+            let (kept, end) = kept.split_at(kept.len() - 1);
+            assert!(first.starts_with(kept), "size {}: {:?}", size, kept);
+            assert_eq!(end, b"\n");
+            assert_eq!(rest_out, rest);
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_runtime_sized_buffers() {
+    use crate::{OverflowPolicy, VecPacker, VecUnpacker};
+    use std::boxed::Box;
+    use std::vec;
+
+    let gcode = b"M117 A message far too long for a small buffer\nG1 X1\n";
+    let mut expected: Vec<u8> = Vec::new();
+    Packer::<128>::pack_slice(gcode, &mut expected, false, false).unwrap();
+
+    // The vec grows to fit the longest line.
+    let meat = pack_all(VecPacker::with_capacity(4, false, false), gcode).unwrap();
+    assert_eq!(expected, meat);
+    assert_eq!(unpack_all(VecUnpacker::default(), &meat).unwrap(), gcode);
+
+    // A boxed slice is sized at runtime but does not grow.
+    let buffer: Box<[u8]> = vec![0u8; 8].into_boxed_slice();
+    let unpacker = BufferedUnpacker::with_buffer(buffer).with_overflow(OverflowPolicy::Truncate);
+    let unpacked = unpack_all(unpacker, gcode).unwrap();
+    assert_eq!(lines_of(&unpacked), ["M117 A \n", "G1 X1\n"]);
+
+    let buffer: Box<[u8]> = vec![0u8; 8].into_boxed_slice();
+    let mut packer = BufferedPacker::with_buffer(buffer, false, false);
+    let err = gcode.iter().find_map(|b| packer.pack(b).err());
+    assert!(matches!(
+        err.map(|e| e.error),
+        Some(MeatPackError::BufferFull)
+    ));
+}
+
+#[test]
+fn test_borrowed_line_buffers() {
+    use crate::MeatPackIterExt;
+
+    let gcode = b"G1 X1 Y2\nM117 Hello\n";
+    let mut pack_buf = [0u8; 32];
+    let packer = BufferedPacker::with_buffer(pack_buf.as_mut_slice(), true, false);
+    let meat = pack_all(packer, gcode).unwrap();
+
+    let mut unpack_buf = [0u8; 32];
+    let mut unpacker = BufferedUnpacker::with_buffer(unpack_buf.as_mut_slice());
+    let mut unpacked: Vec<u8> = Vec::new();
+    for b in meat.iter() {
+        if let MeatPackResult::Line(line) = unpacker.unpack(b).unwrap() {
+            unpacked.extend(line);
+        }
+    }
+    assert_eq!(gcode.as_slice(), unpacked);
+    // The last line remains in the borrowed buffer until the
+    // unpacker moves on.
+    assert_eq!(&unpack_buf[..11], b"M117 Hello\n");
+
+    // The iterator adapters take any line buffer.
+    let mut pack_buf = [0u8; 32];
+    let mut unpack_buf = [0u8; 32];
+    let unpacked: Result<Vec<u8>, _> = gcode
+        .iter()
+        .copied()
+        .meatpack_with(BufferedPacker::with_buffer(
+            pack_buf.as_mut_slice(),
+            true,
+            false,
+        ))
+        .map(Result::unwrap)
+        .meatunpack_with(BufferedUnpacker::with_buffer(unpack_buf.as_mut_slice()))
+        .collect();
+    assert_eq!(gcode.as_slice(), unpacked.unwrap());
+}
+
+#[cfg(feature = "heapless")]
+#[test]
+fn test_heapless_line_buffer() {
+    let gcode = b"G1 X1 Y2\nM117 A message longer than sixteen\n";
+    let packer = BufferedPacker::with_buffer(heapless::Vec::<u8, 64>::new(), true, false);
+    let meat = pack_all(packer, gcode).unwrap();
+
+    let unpacker = BufferedUnpacker::with_buffer(heapless::Vec::<u8, 64>::new());
+    assert_eq!(unpack_all(unpacker, &meat).unwrap(), gcode);
+
+    // It grows no further than its capacity.
+    let mut unpacker = BufferedUnpacker::with_buffer(heapless::Vec::<u8, 16>::new());
+    let err = meat.iter().find_map(|b| unpacker.unpack(b).err());
+    assert!(matches!(
+        err.map(|e| e.error),
+        Some(MeatPackError::BufferFull)
+    ));
+}
+
+#[test]
+fn test_finish_unterminated_final_line() {
+    let gcode = b"G1 X1\nG1 X123";
+
+    for (append_newline, expected) in [
+        (false, b"G1 X1\nG1 X123".as_slice()),
+        (true, b"G1 X1\nG1 X123\n".as_slice()),
+    ] {
+        let mut packer = Packer::<64>::default();
+        let mut meat: Vec<u8> = Vec::new();
+        meat.extend(&MEATPACK_HEADER);
+        for b in gcode.iter() {
+            if let MeatPackResult::Line(line) = packer.pack(b).unwrap() {
+                meat.extend(line);
+            }
+        }
+        // The 3 is held waiting for a partner.
+        assert!(packer.data_remains());
+        meat.extend(packer.finish(append_newline).unwrap().unwrap());
+        assert!(!packer.data_remains());
+        assert!(packer.finish(append_newline).unwrap().is_none());
+        assert_eq!(
+            unpack_all(Unpacker::<64>::default(), &meat).unwrap(),
+            expected
+        );
+    }
+
+    // A fullwidth character held at the end.
+    let mut packer = Packer::<64>::default();
+    let mut meat: Vec<u8> = Vec::new();
+    meat.extend(&MEATPACK_HEADER);
+    for b in b"M1" {
+        packer.pack(b).unwrap();
+    }
+    meat.extend(packer.finish(false).unwrap().unwrap());
+    let mut unpacker = Unpacker::<64>::default();
+    for b in meat.iter() {
+        unpacker.unpack(b).unwrap();
+    }
+    assert_eq!(Some(b"M1".as_slice()), unpacker.finish(false).unwrap());
+
+    // Data ending part way through a character can't be finished.
+    let mut unpacker = Unpacker::<64>::default();
+    for b in MEATPACK_HEADER.iter().chain(&[0b1111_0001]) {
+        unpacker.unpack(b).unwrap();
+    }
+    assert!(unpacker.finish(false).is_err());
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_slices_without_trailing_newline() {
+    let gcode = b"G1 X1\nM117 Done";
+    let mut meat: Vec<u8> = Vec::new();
+    Packer::<64>::pack_slice(gcode, &mut meat, false, false).unwrap();
+    let mut unpacked: Vec<u8> = Vec::new();
+    Unpacker::<64>::unpack_slice(&meat, &mut unpacked).unwrap();
+    assert_eq!(gcode.as_slice(), unpacked);
+}
+
+#[test]
+fn test_lossless_round_trip() {
+    let samples: [&[u8]; 2] = [
+        include_bytes!("../../test_files/box.gcode"),
+        b"; header\n\nG1 X1\t Y2 \r\n\n\nM117 Hi ; note  \r\n\t\nG1 X123",
+    ];
+
+    for gcode in samples {
+        let meat = pack_all(Packer::<128>::default().with_lossless(true), gcode).unwrap();
+        let unpacked = unpack_all(Unpacker::<128>::default().with_lossless(true), &meat).unwrap();
+        assert_eq!(gcode, unpacked);
+    }
+}
+
+#[test]
+fn test_crlf_line_endings() {
+    use crate::LineEnding;
+
+    let gcode = b"G1 X1\r\nM117 A\rB\r\nG1 X2\n";
+
+    // Normalizing drops the fullwidth \r ending the first
+    // two lines but keeps the lone \r.
+    let preserved = pack_all(Packer::<64>::default(), gcode).unwrap();
+    let normalized = pack_all(Packer::<64>::default().with_normalize_crlf(true), gcode).unwrap();
+    assert!(normalized.len() < preserved.len());
+
+    for (meat, line_ending, expected) in [
+        (&preserved, LineEnding::Preserve, gcode.as_slice()),
+        (
+            &normalized,
+            LineEnding::Preserve,
+            b"G1 X1\nM117 A\rB\nG1 X2\n",
+        ),
+        (&preserved, LineEnding::Lf, b"G1 X1\nM117 A\rB\nG1 X2\n"),
+        (
+            &normalized,
+            LineEnding::CrLf,
+            b"G1 X1\r\nM117 A\rB\r\nG1 X2\r\n",
+        ),
+    ] {
+        let unpacker = Unpacker::<64>::default().with_line_ending(line_ending);
+        assert_eq!(unpack_all(unpacker, meat).unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_error_positions() {
+    let gcode = b"G1 X1\nM117 Too long for the buffer\n";
+    let mut packer = Packer::<8>::default();
+    let err = gcode.iter().find_map(|b| packer.pack(b).err()).unwrap();
+    assert!(matches!(err.error, MeatPackError::BufferFull));
+    assert_eq!(2, err.line);
+    assert_eq!(2, packer.line_number());
+    assert_eq!(err.byte + 1, packer.byte_offset());
+    // Somewhere on the second line.
+    assert!(err.byte > 6 && err.byte < gcode.len() - 1);
+
+    let mut meat = pack_all(Packer::<64>::default(), b"G1 X1\nG1 X2\n").unwrap();
+    let bad = meat.len() + 2;
+    meat.extend([255, 255, 1]);
+
+    let mut unpacker = Unpacker::<64>::default();
+    let err = meat.iter().find_map(|b| unpacker.unpack(b).err()).unwrap();
+    assert!(matches!(err.error, MeatPackError::InvalidCommandByte(1)));
+    assert_eq!(3, err.line);
+    assert_eq!(bad, err.byte);
+}
+
+#[test]
+fn test_unpacker_recovery() {
+    use crate::MeatPackCommand;
+
+    // An invalid command part way through the second line
+    // loses the line and the one after it. The pieces after
+    // the first are packed without a header.
+    let header = MEATPACK_HEADER.len();
+    let mut meat = pack_all(Packer::<64>::default(), b"G1 X1\n").unwrap();
+    meat.extend([0b0001_1101, 255, 255, 1]);
+    meat.extend(&pack_all(Packer::<64>::default(), b"G1 X2\nG1 X3\n").unwrap()[header..]);
+    // Waiting for a fullwidth byte when a command arrives.
+    meat.extend([0b0001_1111, 255, 255, 251]);
+    meat.extend(&pack_all(Packer::<64>::default(), b"G1 X4\n").unwrap()[header..]);
+
+    let mut unpacker = Unpacker::<64>::default();
+    assert!(meat.iter().any(|b| unpacker.unpack(b).is_err()));
+
+    let mut unpacker = Unpacker::<64>::default().with_recovery(true);
+    let mut lines: Vec<String> = Vec::new();
+    let mut resynced: Vec<usize> = Vec::new();
+    let mut commands: Vec<MeatPackCommand> = Vec::new();
+    for b in meat.iter() {
+        match unpacker.unpack(b).unwrap() {
+            MeatPackResult::Line(line) => lines.push(String::from(from_utf8(line).unwrap())),
+            MeatPackResult::Resynced(dropped) => resynced.push(dropped),
+            MeatPackResult::Command(cmd) => commands.push(cmd),
+            _ => {}
+        }
+    }
+    assert_eq!(lines, ["G1 X1\n", "G1 X3\n", "G1 X4\n"]);
+    // The four corrupt bytes and the three of the G1 X2 line,
+    // then the lone packed byte ahead of the command.
+    assert_eq!(resynced, [7, 1]);
+    assert_eq!(
+        commands,
+        [
+            MeatPackCommand::PackingEnabled,
+            MeatPackCommand::PackingEnabled
+        ]
+    );
+}
+
+#[test]
+fn test_unpacker_strictness() {
+    use crate::{MeatPackCommand, Strictness};
+
+    let strict = || Unpacker::<64>::default().with_strictness(Strictness::Strict);
+
+    let meat = pack_all(Packer::<64>::default(), b"G1 X1\nM117 Hi\n").unwrap();
+    assert!(unpack_all(strict(), &meat).is_ok());
+    assert!(matches!(
+        unpack_all(strict(), &meat[3..]),
+        Err(MeatPackError::MissingHeader)
+    ));
+    assert!(unpack_all(Unpacker::<64>::default(), &meat[3..]).is_ok());
+
+    // The missing header is reported once, not for every byte.
+    let mut unpacker = strict();
+    let errors = b"G1 X1\nG1 X2\n"
+        .iter()
+        .filter(|b| unpacker.unpack(b).is_err())
+        .count();
+    assert_eq!(errors, 1);
+
+    // So recovery only drops the first line.
+    let unpacked = unpack_all(strict().with_recovery(true), b"G1 X1\nG1 X2\n").unwrap();
+    assert_eq!(lines_of(&unpacked), ["G1 X2\n"]);
+
+    // A fullwidth G could have been packed.
+    let meat = with_header(&[0b0001_1111, b'G', 0b1011_1011]);
+    assert!(matches!(
+        unpack_all(strict(), &meat),
+        Err(MeatPackError::UnneededFullwidth(b'G'))
+    ));
+    assert!(unpack_all(Unpacker::<64>::default(), &meat).is_ok());
+
+    let meat = with_header(&[0b0001_1111, 0, 0b1011_1011]);
+    assert!(matches!(
+        unpack_all(strict(), &meat),
+        Err(MeatPackError::NulByte)
+    ));
+
+    let mut meat = with_header(&MeatPackCommand::PackingDisabled.to_bytes());
+    meat.extend([255, b'M', b'\n']);
+    assert!(matches!(
+        unpack_all(strict(), &meat),
+        Err(MeatPackError::StraySignalByte)
+    ));
+    assert!(unpack_all(Unpacker::<64>::default(), &meat).is_ok());
+
+    let meat = with_header(&[0b0001_1111, b'M']);
+    assert!(matches!(
+        unpack_all(strict(), &meat),
+        Err(MeatPackError::TrailingPartialLine)
+    ));
+    assert!(unpack_all(Unpacker::<64>::default(), &meat).is_ok());
+}
+
+#[test]
+fn test_reserved_bytes() {
+    use crate::ReservedBytePolicy;
+
+    // Reserved bytes are an error by default.
+    let gcode = b"G1 X1\nM117 ab\xFFc\x00d\nG1 X2\n";
+    assert!(matches!(
+        pack_all(Packer::<64>::default(), gcode),
+        Err(MeatPackError::ReservedByte(255))
+    ));
+    assert!(matches!(
+        pack_all(Packer::<64>::default(), b"M117 \x00\n"),
+        Err(MeatPackError::ReservedByte(0))
+    ));
+
+    let packer = Packer::<64>::default().with_reserved_bytes(ReservedBytePolicy::Drop);
+    let meat = pack_all(packer, gcode).unwrap();
+    let unpacked = unpack_all(Unpacker::<64>::default(), &meat).unwrap();
+    assert_eq!(unpacked, b"G1 X1\nM117 abcd\nG1 X2\n");
+
+    // Only the line with the reserved bytes is sent as plain text.
+    let packer = Packer::<64>::default().with_reserved_bytes(ReservedBytePolicy::DisablePacking);
+    let meat = pack_all(packer, gcode).unwrap();
+    assert_eq!(unpack_all(Unpacker::<64>::default(), &meat).unwrap(), gcode);
+    assert!(meat.len() < MEATPACK_HEADER.len() + gcode.len());
+
+    // Two signal bytes in a row would be read as a command.
+    let packer = Packer::<64>::default().with_reserved_bytes(ReservedBytePolicy::DisablePacking);
+    assert!(matches!(
+        pack_all(packer, b"M117 \xFF\xFF\n"),
+        Err(MeatPackError::ReservedByte(255))
+    ));
+}
+
+#[test]
+fn test_detection() {
+    use crate::{Detection, NO_SPACES_COMMAND, detect};
+
+    let gcode = include_bytes!("../../test_files/box.gcode");
+    assert_eq!(detect(gcode), Detection::Plain);
+    assert_eq!(detect(&[]), Detection::Plain);
+
+    // UTF-8 in comments and messages is still text, even when the
+    // prefix cuts a character in half.
+    let utf8 = "; Généré par PrusaSlicer — ü ö ä 日本語 コメント\nG1 X1\nM117 Grüße\n".as_bytes();
+    assert_eq!(detect(utf8), Detection::Plain);
+    assert_eq!(detect(&utf8[..43]), Detection::Plain);
+    assert!(from_utf8(&utf8[..43]).is_err());
+
+    let meat = pack_all(Packer::<128>::default(), gcode).unwrap();
+    let prefix = &meat[..256];
+    assert_eq!(detect(prefix), Detection::Header { no_spaces: false });
+    let headerless = Detection::Headerless { no_spaces: false };
+    assert_eq!(detect(&prefix[3..]), headerless);
+    for len in [16, 64, 512, 4096] {
+        assert_eq!(detect(&meat[3..3 + len]), headerless);
+    }
+
+    let no_spaces = with_header(&NO_SPACES_COMMAND);
+    assert_eq!(detect(&no_spaces), Detection::Header { no_spaces: true });
+
+    // Headerless data unpacks once the unpacker is told.
+    let headerless = &meat[3..];
+    let unpacker = Unpacker::<128>::default().with_detection(detect(headerless));
+    let unpacked = unpack_all(unpacker, headerless).unwrap();
+    assert!(!unpacked.is_empty());
+    assert_eq!(
+        unpacked,
+        unpack_all(Unpacker::<128>::default(), &meat).unwrap()
+    );
+
+    // Without spaces `E0.5` would otherwise come out as ` 0.5`.
+    let meat = pack_all(Packer::<128>::new(true, true), gcode).unwrap();
+    let header = MEATPACK_HEADER.len() + NO_SPACES_COMMAND.len();
+    assert_eq!(
+        detect(&meat[..header]),
+        Detection::Header { no_spaces: true }
+    );
+    let headerless = &meat[header..];
+    for len in [64, 512, 4096] {
+        assert_eq!(
+            detect(&headerless[..len]),
+            Detection::Headerless { no_spaces: true }
+        );
+    }
+    let unpacker = Unpacker::<128>::default().with_detection(detect(headerless));
+    assert_eq!(
+        unpack_all(unpacker, headerless).unwrap(),
+        unpack_all(Unpacker::<128>::default(), &meat).unwrap()
+    );
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_pack_bulk_matches_pack_slice() {
+    use crate::pack_bulk;
+
+    let gcode = include_bytes!("../../test_files/box.gcode");
+    let inputs: [&[u8]; 7] = [
+        gcode,
+        &gcode[..gcode.len() - 1],
+        b"G1 X1\n\n; comment\n  \nM117 odd\r\nG1\tX2 ; trailing\n",
+        b"G1 X12\nM1",
+        b"G1 X12\nM",
+        b"\n\n\n",
+        b";",
+    ];
+    for input in inputs {
+        for (strip_comments, strip_whitespace) in
+            [(false, false), (true, false), (false, true), (true, true)]
+        {
+            let mut expected: Vec<u8> = Vec::new();
+            Packer::<256>::pack_slice(input, &mut expected, strip_comments, strip_whitespace)
+                .unwrap();
+            let mut bulk: Vec<u8> = Vec::new();
+            pack_bulk(input, &mut bulk, strip_comments, strip_whitespace).unwrap();
+            assert_eq!(expected, bulk);
+        }
+    }
+
+    let mut out: Vec<u8> = Vec::new();
+    assert!(matches!(
+        pack_bulk(b"M117 \xFF\n", &mut out, true, false),
+        Err(MeatPackError::ReservedByte(255))
+    ));
+    assert!(matches!(
+        pack_bulk(b"", &mut out, true, false),
+        Err(MeatPackError::EmptyBuffer)
+    ));
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_unpack_bulk_matches_unpack_slice() {
+    use crate::{ReservedBytePolicy, pack_bulk, unpack_bulk};
+
+    let gcode = include_bytes!("../../test_files/box.gcode");
+    let mut inputs: Vec<Vec<u8>> = Vec::new();
+    for (strip_comments, strip_whitespace) in [(false, false), (true, false), (true, true)] {
+        let mut meat: Vec<u8> = Vec::new();
+        pack_bulk(gcode, &mut meat, strip_comments, strip_whitespace).unwrap();
+        inputs.push(meat);
+    }
+    // Lines of plain text between commands.
+    let packer = Packer::<64>::default().with_reserved_bytes(ReservedBytePolicy::DisablePacking);
+    inputs.push(pack_all(packer, b"G1 X1\nM117 a\xFFb\nG1 X2\n\nM1").unwrap());
+    inputs.push(gcode.to_vec());
+    // Ends waiting for a fullwidth character.
+    inputs.push(with_header(&[0b0001_1111]));
+
+    for input in inputs.iter() {
+        let mut expected: Vec<u8> = Vec::new();
+        let expected = Unpacker::<256>::unpack_slice(input, &mut expected).map(|_| expected);
+        let mut bulk: Vec<u8> = Vec::new();
+        let bulk = unpack_bulk(input, &mut bulk).map(|_| bulk);
+        match (expected, bulk) {
+            (Ok(expected), Ok(bulk)) => assert_eq!(expected, bulk),
+            (Err(expected), Err(bulk)) => {
+                assert_eq!(std::format!("{:?}", expected), std::format!("{:?}", bulk))
+            }
+            (expected, bulk) => panic!("{:?} != {:?}", expected.is_ok(), bulk.is_ok()),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_pack_bulk_long_runs() {
+    use crate::pack_bulk;
+
+    // Runs of every length, broken by a fullwidth character at
+    // every position, cover each block width and the tail after it.
+    let packable = b"0123456789. GXE";
+    let mut gcode: Vec<u8> = Vec::new();
+    for len in 1..72 {
+        for broken in 0..=len {
+            for i in 0..len {
+                gcode.push(if i == broken {
+                    b'Y'
+                } else {
+                    packable[(i + len) % packable.len()]
+                });
+            }
+            gcode.push(b'\n');
+        }
+    }
+    for strip_whitespace in [false, true] {
+        let mut expected: Vec<u8> = Vec::new();
+        Packer::<256>::pack_slice(&gcode, &mut expected, false, strip_whitespace).unwrap();
+        let mut bulk: Vec<u8> = Vec::new();
+        pack_bulk(&gcode, &mut bulk, false, strip_whitespace).unwrap();
+        assert_eq!(expected, bulk);
+    }
+}
+
+#[test]
+fn test_line_chunks() {
+    use crate::line_chunks;
+
+    let gcode = include_bytes!("../../test_files/box.gcode");
+    for chunks in [0, 1, 2, 3, 7, 1000, gcode.len() * 2] {
+        let pieces: Vec<&[u8]> = line_chunks(gcode, chunks).collect();
+        assert!(pieces.len() <= chunks.max(1));
+        assert_eq!(pieces.concat(), gcode);
+        for piece in pieces[..pieces.len() - 1].iter() {
+            assert_eq!(piece.last(), Some(&b'\n'));
+        }
+    }
+    let pieces: Vec<&[u8]> = line_chunks(b"G1\nG2\nG3", 3).collect();
+    assert_eq!(pieces, [b"G1\n".as_slice(), b"G2\n", b"G3"]);
+    assert_eq!(line_chunks(b"", 4).count(), 0);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_pack_parallel() {
+    use crate::{VecPacker, pack_parallel};
+
+    let gcode = include_bytes!("../../test_files/box.gcode");
+    let inputs: [&[u8]; 2] = [gcode, &gcode[..gcode.len() - 1]];
+    for input in inputs {
+        for (strip_comments, strip_whitespace) in [(true, false), (false, true)] {
+            let mut expected: Vec<u8> = Vec::new();
+            Packer::<256>::pack_slice(input, &mut expected, strip_comments, strip_whitespace)
+                .unwrap();
+            let mut packer = Packer::<256>::new(strip_comments, strip_whitespace);
+            let mut lines = input
+                .iter()
+                .filter(|b| matches!(packer.pack(b), Ok(MeatPackResult::Line(_))))
+                .count();
+            lines += usize::from(packer.finish(false).unwrap().is_some());
+            for jobs in [0, 1, 2, 3, 16] {
+                let mut parallel: Vec<u8> = Vec::new();
+                let packed = pack_parallel(input, &mut parallel, jobs, false, || {
+                    VecPacker::with_capacity(64, strip_comments, strip_whitespace)
+                })
+                .unwrap();
+                assert_eq!(expected, parallel);
+                assert_eq!(lines, packed);
+            }
+        }
+    }
+
+    // The final line can be terminated.
+    let unterminated = &gcode[..gcode.len() - 1];
+    let mut terminated: Vec<u8> = Vec::new();
+    pack_parallel(gcode, &mut terminated, 1, false, Packer::<256>::default).unwrap();
+    for jobs in [1, 4] {
+        let mut parallel: Vec<u8> = Vec::new();
+        pack_parallel(
+            unterminated,
+            &mut parallel,
+            jobs,
+            true,
+            Packer::<256>::default,
+        )
+        .unwrap();
+        assert_eq!(terminated, parallel);
+    }
+
+    // Errors are placed in the whole of the gcode.
+    let mut bad = gcode.to_vec();
+    let at = bad.len() * 3 / 4;
+    bad[at] = 0xFF;
+    let mut packer = Packer::<256>::default();
+    let expected = bad.iter().find_map(|b| packer.pack(b).err()).unwrap();
+    let mut out: Vec<u8> = Vec::new();
+    let err = pack_parallel(&bad, &mut out, 4, false, Packer::<256>::default).unwrap_err();
+    assert!(matches!(err.error, MeatPackError::ReservedByte(255)));
+    assert_eq!((expected.line, expected.byte), (err.line, err.byte));
+    assert_eq!(at, err.byte);
+}
@@ -1,181 +1,367 @@
+use crate::components::buffer::LineBuffer;
+use crate::components::config::MeatPackConfig;
+use crate::components::detect::Detection;
+use crate::components::meat::is_signal_byte;
 use crate::components::meat::{
-    MeatPackCommand, MeatPackError, MeatPackResult, Pack, determine_command, is_signal_byte,
+    CARRIAGE_RETURN_BYTE, LINEFEED_BYTE, LineEnding, MeatPackError, MeatPackResult, OverflowPolicy,
+    PositionedError, Progress, Strictness,
 };
+use crate::components::stream_unpack::{StreamUnpacker, Unpacked};
 
 #[cfg(feature = "alloc")]
 use alloc::vec::Vec;
 
-/// A list of state the Unpacker struct can exist in.
-#[derive(Debug)]
-pub enum UnpackerState {
-    FirstCommandByte,
-    SecondCommandByte,
-    RightFullWidthByte,
-    LeftFullWidthByte,
-    Enabled,
-    Disabled,
-}
+/// The most characters a single packed byte can emit,
+/// the last of which may be the \n ending a line.
+const EMITTED_MAX: usize = 2;
+
+/// An unpacker whose line capacity is fixed at compile time.
+pub type Unpacker<const S: usize> = BufferedUnpacker<[u8; S]>;
+
+/// An unpacker whose line buffer is allocated at runtime
+/// and grows to fit the longest line.
+#[cfg(feature = "alloc")]
+pub type VecUnpacker = BufferedUnpacker<Vec<u8>>;
 
 /// A  struct for that unpacks bytes and emits
-/// lines of gcode.
-pub struct Unpacker<const S: usize> {
-    state: UnpackerState,
-    no_spaces: bool,
+/// lines of gcode. It buffers the output of a
+/// `StreamUnpacker` into lines held in a `LineBuffer`.
+/// Lines longer than the buffer are handled according
+/// to its `OverflowPolicy`.
+pub struct BufferedUnpacker<B: LineBuffer> {
+    stream: StreamUnpacker,
+    overflow: OverflowPolicy,
+    line_ending: LineEnding,
+    recovery: bool,
+    resyncing: bool,
+    line_start: usize,
+    truncating: bool,
+    overflowed: bool,
     clear: bool,
     pos: usize,
-    inner: [u8; S],
+    inner: B,
+    drain: usize,
+    lines: usize,
+    bytes: usize,
 }
 
 impl<const S: usize> Default for Unpacker<S> {
     /// The default implementation of the unpacker.
     fn default() -> Self {
+        Self::with_buffer([0u8; S])
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Default for VecUnpacker {
+    /// The default implementation of the vec unpacker.
+    fn default() -> Self {
+        Self::with_buffer(Vec::new())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl VecUnpacker {
+    /// Create a new instance of the unpacker with room for
+    /// `capacity` bytes of a line to start with.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_buffer(alloc::vec![0u8; capacity])
+    }
+}
+
+impl<B: LineBuffer> BufferedUnpacker<B> {
+    /// Create a new instance of the unpacker that builds
+    /// its lines in `buffer`.
+    pub fn with_buffer(buffer: B) -> Self {
         Self {
-            state: UnpackerState::Disabled,
-            no_spaces: false,
+            stream: StreamUnpacker::default(),
+            overflow: OverflowPolicy::default(),
+            line_ending: LineEnding::default(),
+            recovery: false,
+            resyncing: false,
+            line_start: 0,
+            truncating: false,
+            overflowed: false,
             clear: false,
             pos: 0,
-            inner: [0u8; S],
+            inner: buffer,
+            drain: 0,
+            lines: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Sets what happens when an unpacked line does not fit in
+    /// the buffer.
+    pub fn with_overflow(
+        mut self,
+        overflow: OverflowPolicy,
+    ) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Keeps empty lines, as sent by a lossless packer, rather
+    /// than dropping them.
+    pub fn with_lossless(
+        mut self,
+        lossless: bool,
+    ) -> Self {
+        self.stream = self.stream.with_lossless(lossless);
+        self
+    }
+
+    /// Starts in the mode a `detect` found the data to be in
+    /// rather than waiting for a header.
+    pub fn with_detection(
+        mut self,
+        detection: Detection,
+    ) -> Self {
+        self.stream = self.stream.with_detection(detection);
+        self
+    }
+
+    /// Sets how closely the data is checked against the protocol.
+    pub fn with_strictness(
+        mut self,
+        strictness: Strictness,
+    ) -> Self {
+        self.stream = self.stream.with_strictness(strictness);
+        self
+    }
+
+    /// Sets the line ending given to the unpacked lines.
+    pub fn with_line_ending(
+        mut self,
+        line_ending: LineEnding,
+    ) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Recovers from corrupt data rather than erroring. The line
+    /// being unpacked is discarded and bytes are dropped until the
+    /// next newline or command sequence, at which point
+    /// `MeatPackResult::Resynced` reports how many were dropped.
+    pub fn with_recovery(
+        mut self,
+        recovery: bool,
+    ) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    /// The number of bytes a line ending takes up.
+    fn line_ending_len(&self) -> usize {
+        match self.line_ending {
+            LineEnding::CrLf => 2,
+            _ => 1,
         }
     }
-}
 
-impl<const S: usize> Unpacker<S> {
     /// Unpacks a single meatpacked byte checking on the
     /// history of the previously unpacked items. It returns
-    /// detailing what it is waiting for next.
+    /// detailing what it is waiting for next. Errors carry
+    /// the position of the byte in the meatpacked data, and
+    /// `UndrainedLine` while `unpack_into` holds a completed line.
     pub fn unpack(
         &mut self,
         byte: &u8,
-    ) -> Result<MeatPackResult, MeatPackError> {
+    ) -> Result<MeatPackResult<'_>, PositionedError> {
+        let (line, offset) = (self.line_number(), self.bytes);
+        self.check_drained()?;
+        self.bytes += 1;
+        self.unpack_byte(byte).map_err(|error| PositionedError {
+            error,
+            line,
+            byte: offset,
+        })
+    }
+
+    /// The line, counting from 1, currently being unpacked.
+    pub fn line_number(&self) -> usize {
+        self.lines + 1
+    }
+
+    /// The number of meatpacked bytes unpacked so far, which
+    /// is the offset of the next byte.
+    pub fn byte_offset(&self) -> usize {
+        self.bytes
+    }
+
+    /// Unpacks a single meatpacked byte.
+    fn unpack_byte(
+        &mut self,
+        byte: &u8,
+    ) -> Result<MeatPackResult<'_>, MeatPackError> {
         if self.clear {
             self.clear()
         }
-
-        // First check if it is a signal byte
-        // and handle the scenarios.
-        if is_signal_byte(byte) {
-            match self.state {
-                UnpackerState::FirstCommandByte => {
-                    self.state = UnpackerState::SecondCommandByte;
-                    return Ok(MeatPackResult::WaitingForNextByte);
-                }
-                UnpackerState::Disabled => {
-                    self.state = UnpackerState::FirstCommandByte;
-                    return Ok(MeatPackResult::WaitingForNextByte);
-                }
-                UnpackerState::Enabled => {
-                    self.state = UnpackerState::FirstCommandByte;
-                    return Ok(MeatPackResult::WaitingForNextByte);
-                }
-                _ => {
-                    self.pos = 0;
-                    self.inner.fill(0);
-                    return Err(MeatPackError::InvalidState);
-                }
-            }
+        if self.resyncing {
+            return Ok(self.resync(byte));
         }
 
-        // Handle non signal scenarios.
-        match self.state {
-            UnpackerState::Disabled => {
-                // If a normal new line.
-                // Return the line for further processing.
-                self.push(byte)?;
-                if *byte == 10 {
-                    self.clear = true; // clear buffer next time round.
-                    Ok(MeatPackResult::Line(self.return_slice()))
-                } else {
-                    Ok(MeatPackResult::WaitingForNextByte)
-                }
+        let chars = match self.stream.unpack(byte) {
+            Ok(Unpacked::Chars(chars)) => chars,
+            Ok(Unpacked::Command(cmd)) => {
+                self.line_start = self.bytes;
+                return Ok(MeatPackResult::Command(cmd));
             }
-            UnpackerState::Enabled => {
-                let (most, least) = byte.unpack(self.no_spaces);
-
-                // most, least
-                // Check if we need to wait for a
-                // fullwidth byte.
-                match (most, least) {
-                    // \n\n packed byte. Just return one \n
-                    (10, 10) => {
-                        self.push(&10)?;
-                    }
-                    // most is a full width byte
-                    (0, 1..) => {
-                        self.push(&least)?;
-                        self.state = UnpackerState::RightFullWidthByte;
-                    }
-                    // least is a full width byte
-                    (1.., 0) => {
-                        self.push(&least)?;
-                        self.push(&most)?;
-                        self.state = UnpackerState::LeftFullWidthByte;
-                    }
-                    // Should be dealt with by the command bytes section.
-                    (0, 0) => {
-                        unreachable!();
-                    }
-                    // Two unpacked packable bytes.
-                    (most, least) => {
-                        self.push(&least)?;
-                        self.push(&most)?;
-                    }
-                }
-
-                // Check if we're ready to check the last byte
-                // for a return value.
-                match self.state {
-                    UnpackerState::Enabled => {
-                        if self.inner[self.pos - 1] == 10 && self.pos > 1 {
-                            self.clear = true; // clear buffer next time round.
-                            return Ok(MeatPackResult::Line(self.return_slice()));
-                        }
-                        // empty line
-                        if self.inner[self.pos - 1] == 10 {
-                            self.clear = true; // clear buffer next time round.
-                        }
-                        Ok(MeatPackResult::WaitingForNextByte)
-                    }
-                    _ => Ok(MeatPackResult::WaitingForNextByte),
-                }
+            Err(_) if self.recovery => {
+                // Drop the corrupt line and look for somewhere to
+                // pick up again, which may be this very byte.
+                self.pos = 0;
+                self.truncating = false;
+                self.overflowed = false;
+                self.resyncing = true;
+                self.stream.resync();
+                return Ok(self.resync(byte));
             }
-            UnpackerState::SecondCommandByte => {
-                let cmd = determine_command(byte)?;
-                self.handle_command(cmd);
-                Ok(MeatPackResult::WaitingForNextByte)
+            Err(MeatPackError::InvalidState) => {
+                self.pos = 0;
+                self.inner.as_bytes_mut().fill(0);
+                self.stream.discard_line();
+                return Err(MeatPackError::InvalidState);
             }
-            UnpackerState::FirstCommandByte => {
-                self.state = UnpackerState::RightFullWidthByte;
-                self.push(byte)?;
-                Ok(MeatPackResult::WaitingForNextByte)
+            Err(e) => return Err(e),
+        };
+        let line_end = chars.last() == Some(&LINEFEED_BYTE);
+        // Skip the rest of a line that did not fit.
+        if self.overflowed {
+            if line_end {
+                self.overflowed = false;
+                self.lines += 1;
+                self.line_start = self.bytes;
             }
-            UnpackerState::RightFullWidthByte => {
-                self.state = UnpackerState::Enabled;
-                self.push(byte)?;
-                Ok(MeatPackResult::WaitingForNextByte)
+            return Ok(MeatPackResult::WaitingForNextByte);
+        }
+        for c in chars.iter() {
+            // When truncating keep room for the line ending
+            // and drop everything else until it arrives.
+            if self.overflow == OverflowPolicy::Truncate
+                && *c != LINEFEED_BYTE
+                && (self.truncating || !self.inner.make_room(self.pos + 1 + self.line_ending_len()))
+            {
+                self.truncating = true;
+                continue;
             }
-            UnpackerState::LeftFullWidthByte => {
-                self.state = UnpackerState::Enabled;
-                self.inner[self.pos - 2] = *byte;
-                if self.inner[self.pos - 1] == 10 {
-                    self.clear = true; // clear buffer next time round.
-                    return Ok(MeatPackResult::Line(self.return_slice()));
-                }
-                Ok(MeatPackResult::WaitingForNextByte)
+            let pushed = if *c == LINEFEED_BYTE {
+                self.end_line().and_then(|()| self.push(c))
+            } else {
+                self.push(c)
+            };
+            if let Err(e) = pushed {
+                return Err(self.drop_line(e, line_end));
             }
         }
+
+        // Return the line for further processing
+        // once its \n has been unpacked.
+        if line_end {
+            self.lines += 1;
+            self.line_start = self.bytes;
+            self.truncating = false;
+            self.clear = true; // clear buffer next time round.
+            return Ok(MeatPackResult::Line(self.return_slice()));
+        }
+        // Hand out what we have if the next byte might not fit.
+        if self.overflow == OverflowPolicy::Partial
+            && self.pos > 0
+            && !self
+                .inner
+                .make_room(self.pos + EMITTED_MAX - 1 + self.line_ending_len())
+        {
+            self.clear = true;
+            return Ok(MeatPackResult::Partial(self.return_slice()));
+        }
+        Ok(MeatPackResult::WaitingForNextByte)
+    }
+
+    /// The current configuration, as reported in reply to a
+    /// `QueryConfig` with `MeatPackConfig::report`.
+    pub fn config(&self) -> MeatPackConfig {
+        self.stream.config()
     }
 
     /// Clears the internal buffer and resets the
     /// write position into the internal buffer.
     fn clear(&mut self) {
-        self.inner.fill(0);
+        self.inner.as_bytes_mut().fill(0);
         self.pos = 0;
         self.clear = false;
     }
 
     /// Returns a slice of the filled elements in the buffer.
     fn return_slice(&mut self) -> &[u8] {
-        &self.inner[0..self.pos]
+        &self.inner.as_bytes()[0..self.pos]
+    }
+
+    /// Drops bytes after corrupt data until a newline has been
+    /// decoded or a command sequence starts.
+    fn resync(
+        &mut self,
+        byte: &u8,
+    ) -> MeatPackResult<'_> {
+        if is_signal_byte(byte) {
+            // Decode the command sequence from its first byte.
+            let dropped = self.bytes - 1 - self.line_start;
+            self.line_start = self.bytes - 1;
+            self.resyncing = false;
+            self.stream.resync();
+            let _ = self.stream.unpack(byte);
+            return MeatPackResult::Resynced(dropped);
+        }
+        match self.stream.unpack(byte) {
+            Ok(Unpacked::Chars(chars)) if chars.last() == Some(&LINEFEED_BYTE) => {
+                let dropped = self.bytes - self.line_start;
+                self.line_start = self.bytes;
+                self.lines += 1;
+                self.resyncing = false;
+                self.stream.resync();
+                MeatPackResult::Resynced(dropped)
+            }
+            Ok(_) => MeatPackResult::WaitingForNextByte,
+            Err(_) => {
+                self.stream.resync();
+                MeatPackResult::WaitingForNextByte
+            }
+        }
+    }
+
+    /// Drops a line that does not fit the buffer and, unless it
+    /// has just ended, skips the rest of it so the following
+    /// lines can still be unpacked.
+    fn drop_line(
+        &mut self,
+        error: MeatPackError,
+        line_end: bool,
+    ) -> MeatPackError {
+        self.pos = 0;
+        self.overflowed = !line_end;
+        if line_end {
+            self.lines += 1;
+            self.line_start = self.bytes;
+        }
+        error
+    }
+
+    /// Gives the line the chosen line ending ahead of its \n.
+    fn end_line(&mut self) -> Result<(), MeatPackError> {
+        let cr = self.pos > 0 && self.inner.as_bytes()[self.pos - 1] == CARRIAGE_RETURN_BYTE;
+        match self.line_ending {
+            LineEnding::Preserve => {}
+            LineEnding::Lf => {
+                if cr {
+                    self.pos -= 1;
+                }
+            }
+            LineEnding::CrLf => {
+                if !cr {
+                    self.push(&CARRIAGE_RETURN_BYTE)?;
+                }
+            }
+        }
+        Ok(())
     }
 
     /// Push a byte to the internal buffer.
@@ -183,54 +369,148 @@ impl<const S: usize> Unpacker<S> {
         &mut self,
         byte: &u8,
     ) -> Result<(), MeatPackError> {
-        if self.pos > S {
+        if !self.inner.make_room(self.pos + 1) {
             return Err(MeatPackError::BufferFull);
         }
-        self.inner[self.pos] = *byte;
+        self.inner.as_bytes_mut()[self.pos] = *byte;
         self.pos += 1;
         Ok(())
     }
 
-    /// Handles the command byte combinations that
-    /// exist in the meatpack spec.
-    fn handle_command(
+    /// The number of unpacked bytes held for the current
+    /// line that have not yet been returned.
+    pub(crate) fn pending(&self) -> usize {
+        if self.clear { 0 } else { self.pos }
+    }
+
+    /// Returns the most recently completed line. This remains
+    /// valid until the next byte is unpacked.
+    #[cfg(feature = "std")]
+    pub(crate) fn line(&self) -> &[u8] {
+        &self.inner.as_bytes()[0..self.pos]
+    }
+
+    /// A utility function to check if any data remains
+    /// in the internal buffer. We expect all meatpack
+    /// lines to newline end, otherwise call `finish`.
+    pub fn data_remains(&self) -> bool {
+        self.pending() > 0
+    }
+
+    /// Returns whatever remains of an unterminated final line.
+    /// With `append_newline` the line is terminated with a `\n`.
+    /// Returns `None` if the last line was already complete and
+    /// errors if the data ends part way through a character, or
+    /// with `UndrainedLine` while `unpack_into` holds a line.
+    pub fn finish(
         &mut self,
-        cmd: MeatPackCommand,
-    ) {
-        match cmd {
-            MeatPackCommand::PackingEnabled => {
-                self.state = UnpackerState::Enabled;
-            }
-            MeatPackCommand::PackingDisabled => {
-                self.state = UnpackerState::Disabled;
-            }
-            MeatPackCommand::ResetAll => {
-                self.state = UnpackerState::Disabled;
-                self.no_spaces = false;
+        append_newline: bool,
+    ) -> Result<Option<&[u8]>, PositionedError> {
+        let (line, byte) = (self.line_number(), self.bytes);
+        self.check_drained()?;
+        self.finish_line(append_newline)
+            .map_err(|error| PositionedError { error, line, byte })
+    }
+
+    /// Unpacks the final line.
+    fn finish_line(
+        &mut self,
+        append_newline: bool,
+    ) -> Result<Option<&[u8]>, MeatPackError> {
+        if self.clear {
+            self.clear()
+        }
+        self.truncating = false;
+        let mid_line = self.stream.mid_line();
+        if let Err(e) = self.stream.finish() {
+            self.clear();
+            self.overflowed = false;
+            return Err(e);
+        }
+        if self.overflowed {
+            self.overflowed = false;
+            return Ok(None);
+        }
+        if append_newline && mid_line {
+            self.end_line()?;
+            self.push(&LINEFEED_BYTE)?;
+        }
+        if self.pos == 0 {
+            return Ok(None);
+        }
+        self.clear = true;
+        Ok(Some(self.return_slice()))
+    }
+
+    /// Unpacks as much of `in_buf` as will fit into `out_buf`
+    /// without allocating.
+    ///
+    /// Completed lines that do not fit are held by the unpacker and
+    /// written out first on the next call, so the call can be resumed
+    /// with `&in_buf[progress.consumed..]` and a fresh output buffer.
+    /// Pass an empty `in_buf` to drain what is left. Lines that are
+    /// not yet terminated remain in the unpacker until `finish_into`.
+    pub fn unpack_into(
+        &mut self,
+        in_buf: &[u8],
+        out_buf: &mut [u8],
+    ) -> Result<Progress, PositionedError> {
+        let mut progress = Progress::default();
+        loop {
+            // Write out what remains of the last completed line.
+            if self.drain > 0 {
+                let line = &self.inner.as_bytes()[self.pos - self.drain..self.pos];
+                let n = line.len().min(out_buf.len() - progress.written);
+                out_buf[progress.written..progress.written + n].copy_from_slice(&line[..n]);
+                progress.written += n;
+                self.drain -= n;
+                if self.drain > 0 {
+                    return Ok(progress);
+                }
             }
-            MeatPackCommand::QueryConfig => {}
-            MeatPackCommand::NoSpacesEnabled => {
-                self.no_spaces = true;
-                self.state = UnpackerState::Enabled;
+            if progress.consumed == in_buf.len() {
+                return Ok(progress);
             }
-            MeatPackCommand::NoSpacesDisabled => {
-                self.no_spaces = false;
-                self.state = UnpackerState::Enabled;
+            if let MeatPackResult::Line(line) | MeatPackResult::Partial(line) =
+                self.unpack(&in_buf[progress.consumed])?
+            {
+                self.drain = line.len();
             }
-            MeatPackCommand::SignalByte => {}
+            progress.consumed += 1;
         }
     }
 
-    /// A utility function to check if any data remains
-    /// in the internal buffer. We expect all meatpack
-    /// lines to newline end.
-    pub fn data_remains(&self) -> bool {
-        if !self.clear || self.pos == 0 {
-            return true;
+    /// Writes out what `unpack_into` holds followed by an
+    /// unterminated final line, returning the number of bytes
+    /// written. Call it with a fresh output buffer until it
+    /// returns 0.
+    pub fn finish_into(
+        &mut self,
+        out_buf: &mut [u8],
+    ) -> Result<usize, PositionedError> {
+        let mut written = self.unpack_into(&[], out_buf)?.written;
+        if self.drain == 0 {
+            self.drain = self.finish(false)?.map_or(0, <[u8]>::len);
+            written += self.unpack_into(&[], &mut out_buf[written..])?.written;
         }
-        false
+        Ok(written)
     }
 
+    /// Errors if a completed line is still to be written out by
+    /// `unpack_into`, as unpacking on would write over it.
+    fn check_drained(&self) -> Result<(), PositionedError> {
+        if self.drain > 0 {
+            return Err(PositionedError {
+                error: MeatPackError::UndrainedLine(self.drain),
+                line: self.line_number(),
+                byte: self.bytes,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<const S: usize> Unpacker<S> {
     /// A convenience function around unpacker that enables you
     /// to simply unpack meapacked data from a slice to a vec.
     #[cfg(feature = "alloc")]
@@ -241,18 +521,18 @@ impl<const S: usize> Unpacker<S> {
         let mut unpacker = Unpacker::<S>::default();
         for b in in_buf {
             match unpacker.unpack(b) {
-                Ok(MeatPackResult::Line(line)) => out_buf.extend(line),
+                Ok(MeatPackResult::Line(line) | MeatPackResult::Partial(line)) => {
+                    out_buf.extend(line)
+                }
                 Ok(MeatPackResult::WaitingForNextByte) => {}
-                Err(e) => return Err(e),
+                Ok(MeatPackResult::Command(_)) => {}
+                Ok(MeatPackResult::Resynced(_)) => {}
+                Err(e) => return Err(e.error),
             }
         }
-        // if the packer is in the state of clearing itself
-        // on the next iteration then ignore as we hit a new line.
-        // Otherwise we have an unterminated line with some
-        // data possibly stuck in the buffer and we're expecting
-        // to end with a terminated line so throw an err.
-        if unpacker.data_remains() {
-            return Err(MeatPackError::UnterminatedLine(unpacker.pos));
+        // Pick up a final line that is not terminated.
+        if let Some(line) = unpacker.finish(false)? {
+            out_buf.extend(line);
         }
         Ok(())
     }
@@ -1,7 +1,7 @@
 #![doc = include_str!("../README.md")]
 #![no_std]
 
-#[cfg(test)]
+#[cfg(any(test, feature = "std"))]
 extern crate std;
 
 #[cfg(feature = "alloc")]
@@ -9,8 +9,36 @@ extern crate alloc;
 
 mod components;
 
+pub use components::buffer::LineBuffer;
+#[cfg(feature = "alloc")]
+pub use components::bulk::{pack_bulk, unpack_bulk};
+pub use components::config::{MeatPackConfig, PROTOCOL_VERSION};
+#[cfg(feature = "std")]
+pub use components::detect::detect_reader;
+pub use components::detect::{Detection, detect};
+pub use components::iter::{MeatPackIterExt, PackIter, UnpackIter};
+pub use components::meat::Emitted;
+pub use components::meat::LineEnding;
+pub use components::meat::MeatPackCommand;
 pub use components::meat::MeatPackError;
 pub use components::meat::MeatPackResult;
-pub use components::meat::{MEATPACK_HEADER, NO_SPACES_COMMAND};
-pub use components::pack::Packer;
-pub use components::unpack::Unpacker;
+pub use components::meat::OverflowPolicy;
+pub use components::meat::PositionedError;
+pub use components::meat::Progress;
+pub use components::meat::ReservedBytePolicy;
+pub use components::meat::Strictness;
+pub use components::meat::{MEATPACK_HEADER, NO_SPACES_COMMAND, determine_command};
+#[cfg(feature = "alloc")]
+pub use components::pack::VecPacker;
+pub use components::pack::{BufferedPacker, Packer};
+#[cfg(feature = "std")]
+pub use components::parallel::pack_parallel;
+pub use components::parallel::{LineChunks, line_chunks};
+pub use components::stream_pack::StreamPacker;
+pub use components::stream_unpack::{StreamUnpacker, Unpacked};
+#[cfg(feature = "alloc")]
+pub use components::unpack::VecUnpacker;
+pub use components::unpack::{BufferedUnpacker, Unpacker};
+
+#[cfg(feature = "std")]
+pub use components::io::{MeatPackReader, MeatPackWriter};
@@ -1,11 +1,21 @@
-use clap::{Parser, Subcommand};
-use meatpack::{MEATPACK_HEADER, MeatPackResult, NO_SPACES_COMMAND, Packer, Unpacker};
+use clap::{Args, Parser, Subcommand};
+use meatpack::{
+    Detection, MEATPACK_HEADER, MeatPackResult, NO_SPACES_COMMAND, Packer, PositionedError,
+    Strictness, Unpacker, detect, pack_parallel,
+};
 use std::{
     fs::File,
-    io::{BufReader, BufWriter, Read, Write},
-    path::PathBuf,
-    process,
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    thread,
+    time::{Duration, Instant},
 };
+use thiserror::Error;
+
+/// The number of bytes read from the input at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
 
 /// Command line options
 #[derive(Debug, Parser)]
@@ -18,135 +28,339 @@ struct Cli {
 /// The different commands that can be parsed
 #[derive(Debug, Subcommand)]
 enum Command {
-    Pack {
-        #[arg(long, default_value_t = false)]
-        strip_comments: bool,
-        #[arg(long, default_value_t = false)]
-        strip_whitespace: bool,
-        infile: PathBuf,
-        outfile: PathBuf,
-    },
-    Unpack {
-        infile: PathBuf,
-        outfile: PathBuf,
+    Pack(PackArgs),
+    Unpack(UnpackArgs),
+    Detect { infile: PathBuf },
+}
+
+/// Options for packing a file
+#[derive(Debug, Args)]
+struct PackArgs {
+    #[arg(long, default_value_t = false)]
+    strip_comments: bool,
+    #[arg(long, default_value_t = false)]
+    strip_whitespace: bool,
+    #[arg(long, default_value_t = false)]
+    append_newline: bool,
+    #[arg(long, default_value_t = false)]
+    lossless: bool,
+    /// Pack on this many threads, or one per CPU if 0.
+    #[arg(long, default_value_t = 1)]
+    jobs: usize,
+    infile: PathBuf,
+    outfile: PathBuf,
+}
+
+/// Options for unpacking a file
+#[derive(Debug, Args)]
+struct UnpackArgs {
+    #[arg(long, default_value_t = false)]
+    lossless: bool,
+    #[arg(long, default_value_t = false)]
+    recover: bool,
+    #[arg(long, default_value_t = false)]
+    strict: bool,
+    #[arg(long, default_value_t = false)]
+    detect: bool,
+    infile: PathBuf,
+    outfile: PathBuf,
+}
+
+/// Why a command failed.
+#[derive(Debug, Error)]
+enum CliError {
+    /// The input could not be packed or unpacked.
+    #[error("{}:{}:{}: {}", path.display(), error.line, error.byte, error.error)]
+    Data {
+        path: PathBuf,
+        error: PositionedError,
     },
+    /// A file could not be opened, read or written.
+    #[error("{}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl CliError {
+    /// Invalid input exits with 1 and failed I/O with 3, leaving
+    /// 2 for the usage errors reported by clap.
+    fn exit_code(&self) -> ExitCode {
+        match self {
+            CliError::Data { .. } => ExitCode::from(1),
+            CliError::Io { .. } => ExitCode::from(3),
+        }
+    }
+}
+
+/// Attaches the path of the input or output file to an I/O error.
+fn io_error(path: &Path) -> impl Fn(io::Error) -> CliError + '_ {
+    move |source| CliError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// Attaches the path of the input file to a packing error.
+fn data_error(path: &Path) -> impl Fn(PositionedError) -> CliError + '_ {
+    move |error| CliError::Data {
+        path: path.to_path_buf(),
+        error,
+    }
+}
+
+/// What a command got through.
+#[derive(Default)]
+struct Stats {
+    lines: usize,
+    read: usize,
+    written: usize,
+    elapsed: Duration,
+}
+
+impl Stats {
+    /// Megabytes of input handled per second.
+    fn throughput(&self) -> f64 {
+        self.read as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON) / 1_000_000.0
+    }
 }
 
 /// CLI
-fn main() {
+fn main() -> ExitCode {
     println!("MeatPack!");
     let cli = Cli::parse();
 
-    match &cli.command {
-        Some(Command::Pack {
-            strip_comments,
-            strip_whitespace,
-            infile,
-            outfile,
-        }) => {
-            println!(
-                "Packing {} into {}",
-                infile.to_str().unwrap(),
-                outfile.to_str().unwrap()
-            );
-            println!("Strip Comments: {}", strip_comments);
-            println!("Strip Whitespace: {}", strip_whitespace);
-
-            let infile = File::open(infile).unwrap();
-            let mut reader = BufReader::new(infile);
-
-            let outfile = File::create(outfile).unwrap();
-            let mut writer = BufWriter::new(outfile);
-
-            let mut packer = Packer::<128>::new(*strip_comments, *strip_whitespace);
-
-            writer.write_all(&MEATPACK_HEADER).unwrap();
-            if *strip_whitespace {
-                writer.write_all(&NO_SPACES_COMMAND).unwrap();
-            }
+    let result = match &cli.command {
+        Some(Command::Pack(args)) => pack(args),
+        Some(Command::Unpack(args)) => unpack(args),
+        Some(Command::Detect { infile }) => detect_file(infile),
+        None => {
+            println!("Please provide a subcommand --pack or --unpack");
+            Ok(())
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            e.exit_code()
+        }
+    }
+}
 
-            let mut line_count: usize = 0;
-            let mut unpacked_byte_count: usize = 0;
-            let mut packed_byte_count: usize = 0;
-            let mut byte: [u8; 1] = [0];
-            while reader.read_exact(byte.as_mut_slice()).is_ok() {
-                unpacked_byte_count += 1;
-                match packer.pack(&byte[0]) {
-                    Ok(MeatPackResult::Line(line)) => {
-                        line_count += 1;
-                        packed_byte_count += line.len();
-                        writer.write_all(line).unwrap();
-                    }
-                    Ok(MeatPackResult::WaitingForNextByte) => {}
-                    Err(e) => {
-                        println!("{:?}", e);
-                        process::exit(1);
-                    }
-                }
-            }
+/// Reads `reader` a chunk at a time until it runs out.
+fn for_each_chunk(
+    reader: &mut impl Read,
+    path: &Path,
+    mut f: impl FnMut(&[u8]) -> Result<(), CliError>,
+) -> Result<(), CliError> {
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => f(&buf[..n])?,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(io_error(path)(e)),
+        }
+    }
+}
 
-            if packer.data_remains() {
-                eprintln!(
-                    "Data remains in the packer. Please make sure the last line is terminated with a new line."
-                )
-            }
+/// Packs a file.
+fn pack(args: &PackArgs) -> Result<(), CliError> {
+    println!(
+        "Packing {} into {}",
+        args.infile.display(),
+        args.outfile.display()
+    );
+    println!("Strip Comments: {}", args.strip_comments);
+    println!("Strip Whitespace: {}", args.strip_whitespace);
+    println!("Lossless: {}", args.lossless);
+
+    let start = Instant::now();
+    // Open the input first so a missing input leaves the output be.
+    let in_error = io_error(&args.infile);
+    let mut infile = File::open(&args.infile).map_err(&in_error)?;
+    let out_error = io_error(&args.outfile);
+    let outfile = File::create(&args.outfile).map_err(&out_error)?;
+    let mut writer = BufWriter::new(outfile);
 
-            println!("Lines Packed: {}", line_count);
-            println!(
-                "{} unpacked bytes -> {} packed bytes ({}%)",
-                unpacked_byte_count,
-                packed_byte_count,
-                (packed_byte_count as f32 / unpacked_byte_count as f32) * 100.0
-            );
+    let mut header = MEATPACK_HEADER.to_vec();
+    if args.strip_whitespace {
+        header.extend(NO_SPACES_COMMAND);
+    }
+    writer.write_all(&header).map_err(&out_error)?;
+
+    let new_packer = || {
+        Packer::<128>::new(args.strip_comments, args.strip_whitespace).with_lossless(args.lossless)
+    };
+    let mut stats = if args.jobs != 1 {
+        let mut stats = Stats::default();
+        let mut gcode = Vec::new();
+        infile.read_to_end(&mut gcode).map_err(&in_error)?;
+        let jobs = match args.jobs {
+            0 => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            jobs => jobs,
+        };
+        println!("Jobs: {}", jobs);
+
+        if !gcode.is_empty() {
+            let mut meat: Vec<u8> = Vec::with_capacity(gcode.len());
+            stats.lines = pack_parallel(&gcode, &mut meat, jobs, args.append_newline, new_packer)
+                .map_err(data_error(&args.infile))?;
+            // The header has already been written.
+            let packed = &meat[header.len()..];
+            stats.read = gcode.len();
+            stats.written = packed.len();
+            writer.write_all(packed).map_err(&out_error)?;
         }
-        Some(Command::Unpack { infile, outfile }) => {
-            println!(
-                "Unpacking {} into {}",
-                infile.to_str().unwrap(),
-                outfile.to_str().unwrap()
-            );
-            let infile = File::open(infile).unwrap();
-            let mut reader = BufReader::new(infile);
-
-            let outfile = File::create(outfile).unwrap();
-            let mut writer = BufWriter::new(outfile);
-
-            let mut unpacker = Unpacker::<128>::default();
-
-            let mut line_count: usize = 0;
-            let mut byte: [u8; 1] = [0];
-            let mut unpacked_byte_count: usize = 0;
-            let mut packed_byte_count: usize = 0;
-            while reader.read_exact(byte.as_mut_slice()).is_ok() {
-                packed_byte_count += 1;
-                match unpacker.unpack(&byte[0]) {
-                    Ok(MeatPackResult::Line(line)) => {
-                        line_count += 1;
-                        unpacked_byte_count += line.len();
-                        writer.write_all(line).unwrap();
+        stats
+    } else {
+        let mut stats = Stats::default();
+        let mut packer = new_packer();
+
+        for_each_chunk(&mut BufReader::new(infile), &args.infile, |chunk| {
+            stats.read += chunk.len();
+            for b in chunk {
+                match packer.pack(b).map_err(data_error(&args.infile))? {
+                    MeatPackResult::Line(line) => {
+                        stats.lines += 1;
+                        stats.written += line.len();
+                        writer.write_all(line).map_err(&out_error)?;
                     }
-                    Ok(MeatPackResult::WaitingForNextByte) => {}
-                    Err(e) => {
-                        println!("{:?}", e);
-                        process::exit(1);
+                    MeatPackResult::Partial(line) => {
+                        stats.written += line.len();
+                        writer.write_all(line).map_err(&out_error)?;
                     }
+                    MeatPackResult::WaitingForNextByte => {}
+                    MeatPackResult::Command(_) => {}
+                    MeatPackResult::Resynced(_) => {}
                 }
             }
+            Ok(())
+        })?;
 
-            if unpacker.data_remains() {
-                eprintln!(
-                    "Data remains in the unpacker. The last line is not terminated with a new line."
-                )
-            }
+        // Pick up a final line without a new line.
+        if let Some(line) = packer
+            .finish(args.append_newline)
+            .map_err(data_error(&args.infile))?
+        {
+            stats.lines += 1;
+            stats.written += line.len();
+            writer.write_all(line).map_err(&out_error)?;
+        }
+        stats
+    };
+    writer.flush().map_err(&out_error)?;
+    stats.elapsed = start.elapsed();
+
+    println!("Lines Packed: {}", stats.lines);
+    println!(
+        "{} unpacked bytes -> {} packed bytes ({}%)",
+        stats.read,
+        stats.written,
+        (stats.written as f32 / stats.read as f32) * 100.0
+    );
+    println!(
+        "Packed in {:.3}s ({:.1} MB/s)",
+        stats.elapsed.as_secs_f64(),
+        stats.throughput()
+    );
+    Ok(())
+}
 
-            println!("Lines unpacked: {}", line_count);
-            println!(
-                "{} packed bytes -> {} unpacked bytes",
-                packed_byte_count, unpacked_byte_count,
-            );
+/// Unpacks a file.
+fn unpack(args: &UnpackArgs) -> Result<(), CliError> {
+    println!(
+        "Unpacking {} into {}",
+        args.infile.display(),
+        args.outfile.display()
+    );
+
+    let start = Instant::now();
+    let in_error = io_error(&args.infile);
+    let infile = File::open(&args.infile).map_err(&in_error)?;
+    let mut reader = BufReader::with_capacity(CHUNK_SIZE, infile);
+
+    let out_error = io_error(&args.outfile);
+    let outfile = File::create(&args.outfile).map_err(&out_error)?;
+    let mut writer = BufWriter::new(outfile);
+
+    let strictness = if args.strict {
+        Strictness::Strict
+    } else {
+        Strictness::Permissive
+    };
+    let mut unpacker = Unpacker::<128>::default()
+        .with_lossless(args.lossless)
+        .with_recovery(args.recover)
+        .with_strictness(strictness);
+    if args.detect {
+        let detection = detect(reader.fill_buf().map_err(&in_error)?);
+        println!("Detected: {:?}", detection);
+        unpacker = unpacker.with_detection(detection);
+    }
+
+    let mut stats = Stats::default();
+    for_each_chunk(&mut reader, &args.infile, |chunk| {
+        stats.read += chunk.len();
+        for b in chunk {
+            match unpacker.unpack(b).map_err(data_error(&args.infile))? {
+                MeatPackResult::Line(line) => {
+                    stats.lines += 1;
+                    stats.written += line.len();
+                    writer.write_all(line).map_err(&out_error)?;
+                }
+                MeatPackResult::Partial(line) => {
+                    stats.written += line.len();
+                    writer.write_all(line).map_err(&out_error)?;
+                }
+                MeatPackResult::WaitingForNextByte => {}
+                MeatPackResult::Command(_) => {}
+                MeatPackResult::Resynced(dropped) => {
+                    eprintln!(
+                        "{}:{}: Dropped {} corrupt bytes",
+                        args.infile.display(),
+                        unpacker.byte_offset() - 1,
+                        dropped
+                    );
+                }
+            }
         }
-        None => {
-            println!("Please provide a subcommand --pack or --unpack");
+        Ok(())
+    })?;
+
+    // Pick up a final line without a new line.
+    if let Some(line) = unpacker.finish(false).map_err(data_error(&args.infile))? {
+        stats.lines += 1;
+        stats.written += line.len();
+        writer.write_all(line).map_err(&out_error)?;
+    }
+    writer.flush().map_err(&out_error)?;
+    stats.elapsed = start.elapsed();
+
+    println!("Lines unpacked: {}", stats.lines);
+    println!(
+        "{} packed bytes -> {} unpacked bytes",
+        stats.read, stats.written,
+    );
+    println!(
+        "Unpacked in {:.3}s ({:.1} MB/s)",
+        stats.elapsed.as_secs_f64(),
+        stats.throughput()
+    );
+    Ok(())
+}
+
+/// Reports whether a file holds packed or plain gcode.
+fn detect_file(infile: &Path) -> Result<(), CliError> {
+    let in_error = io_error(infile);
+    let mut reader = BufReader::new(File::open(infile).map_err(&in_error)?);
+    match detect(reader.fill_buf().map_err(&in_error)?) {
+        Detection::Header { no_spaces: false } => println!("MeatPacked"),
+        Detection::Header { no_spaces: true } => println!("MeatPacked (no spaces)"),
+        Detection::Headerless { no_spaces: false } => println!("MeatPacked (no header)"),
+        Detection::Headerless { no_spaces: true } => {
+            println!("MeatPacked (no header, no spaces)")
         }
+        Detection::Plain => println!("Plain gcode"),
     }
+    Ok(())
 }